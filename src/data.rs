@@ -1,22 +1,104 @@
 use std::fmt;
 use std::cmp::Ordering;
-use std::ops::{Add, Sub, Mul, Div};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Add, Sub, Mul, Div, Range};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
+
+use crate::port::Port;
+use crate::error::{Error, ErrorKind};
+use crate::exec::OPERATORS;
 
 use Value::*;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
     OpenParen,
+    OpenByteVector,
     CloseParen,
     SingleQuote,
+    DatumLabel(u32),
+    DatumRef(u32),
     Keyword(&'static str),
-    Operator(&'static str),
     Ident(&'a str),
-    Num(f32),
+    Int(i64),
+    Num(f64),
     Bool(bool),
+    Char(char),
     Str(String),
+    // text the lexer could not read, standing in for a datum so that parsing can go on
+    Invalid(&'a str),
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::OpenParen => write!(f, "("),
+            Token::OpenByteVector => write!(f, "#u8("),
+            Token::CloseParen => write!(f, ")"),
+            Token::SingleQuote => write!(f, "'"),
+            Token::DatumLabel(label) => write!(f, "#{}=", label),
+            Token::DatumRef(label) => write!(f, "#{}#", label),
+            Token::Keyword(name) => write!(f, "{}", name),
+            Token::Ident(ident) | Token::Invalid(ident) => write!(f, "{}", ident),
+            Token::Int(val) => write!(f, "{}", val),
+            Token::Num(val) => write!(f, "{}", format_flonum(*val)),
+            Token::Bool(val) => write!(f, "{}", if *val { "#t" } else { "#f" }),
+            Token::Char(c) => write!(f, "{}", Value::Char(*c)),
+            Token::Str(s) => write!(f, "{}", Value::str(s.clone())),
+        }
+    }
+}
+
+pub struct Source {
+    pub name: String,
+    pub code: String,
+}
+
+impl Source {
+    pub fn new(name: &str, code: String) -> Rc<Source> {
+        Rc::new(Source { name: name.to_string(), code })
+    }
+}
+
+// a position in a source, as a byte offset into its code
+#[derive(Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub offset: usize,
+}
+
+impl Span {
+    pub fn line_col(&self) -> (usize, usize) {
+        let before = &self.source.code[..self.offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    }
+
+    pub fn starts_line(&self) -> bool {
+        self.offset == 0 || self.source.code[..self.offset].ends_with('\n')
+    }
+
+    // the source line of the span with a caret under its column
+    pub fn excerpt(&self) -> String {
+        let (line, col) = self.line_col();
+        let text = self.source.code.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+        format!("{gutter} |\n{line} | {text}\n{gutter} | {}^", " ".repeat(col - 1))
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (line, col) = self.line_col();
+        write!(f, "{}:{}:{}", self.source.name, line, col)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
 }
 
 #[derive(Debug)]
@@ -39,59 +121,351 @@ pub struct Defn {
 
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Apply { proc: Box<Expr>, args: Vec<Expr> },
-    Lambda { params: Vec<String>, body: Body },
-    Let { binds: Vec<(String, Expr)>, body: Body },
-    LetStar { binds: Vec<(String, Expr)>, body: Body },
-    LetRec { binds: Vec<(String, Expr)>, body: Body },
-    Set { ident: String, expr: Box<Expr> },
-    Var(String),
-    Quote(Box<Value>),
-    Begin(Vec<Expr>),
-    If { cond: Box<Expr>, expr1: Box<Expr>, expr2: Box<Expr> },
-    Cond { cond_then: Vec<(Expr, Expr)> },
-    And { args: Vec<Expr> },
-    Or { args: Vec<Expr> },
-    Do { binds: Vec<(String, Expr, Expr)>, test: Box<Expr>, exprs: Vec<Expr>, body: Box<Expr> },
-    Opr(&'static str),
-    Num(f32),
-    Bool(bool),
-    Str(String),
-    Nil,
+    Apply { proc: Box<Expr>, args: Vec<Expr>, span: Span },
+    Lambda { params: Vec<String>, body: Body, span: Span },
+    Let { binds: Vec<(String, Expr)>, body: Body, span: Span },
+    LetStar { binds: Vec<(String, Expr)>, body: Body, span: Span },
+    LetRec { binds: Vec<(String, Expr)>, body: Body, span: Span },
+    Set { ident: String, expr: Box<Expr>, span: Span },
+    Var(String, Span),
+    Quote(Box<Value>, Span),
+    Begin(Vec<Expr>, Span),
+    If { cond: Box<Expr>, expr1: Box<Expr>, expr2: Box<Expr>, span: Span },
+    Cond { cond_then: Vec<(Expr, Expr)>, span: Span },
+    Guard { var: String, cond_then: Vec<(Expr, Expr)>, body: Body, span: Span },
+    And { args: Vec<Expr>, span: Span },
+    Or { args: Vec<Expr>, span: Span },
+    Do { binds: Vec<(String, Expr, Expr)>, test: Box<Expr>, exprs: Vec<Expr>, body: Box<Expr>, span: Span },
+    Int(i64, Span),
+    Num(f64, Span),
+    Bool(bool, Span),
+    Char(char, Span),
+    Str(String, Span),
+    Nil(Span),
 }
 
-#[derive(Debug, Clone)]
+// the core forms as data, with the derived syntax the parser accepts already expanded
+impl TopLevel {
+    pub fn to_datum(&self) -> Value {
+        match self {
+            TopLevel::Defn(defn) => defn.to_datum(),
+            TopLevel::Expr(expr) => expr.to_datum(),
+        }
+    }
+}
+
+impl Defn {
+    pub fn to_datum(&self) -> Value {
+        Value::list(vec![Value::symbol("define"), Value::symbol(&self.ident), self.expr.to_datum()])
+    }
+}
+
+impl Body {
+    fn to_data(&self) -> Vec<Value> {
+        self.defns.iter().map(Defn::to_datum).chain(self.exprs.iter().map(Expr::to_datum)).collect()
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> &Span {
+        match self {
+            Expr::Apply { span, .. } | Expr::Lambda { span, .. } | Expr::Let { span, .. } | Expr::LetStar { span, .. } | Expr::LetRec { span, .. }
+            | Expr::Set { span, .. } | Expr::If { span, .. } | Expr::Cond { span, .. } | Expr::Guard { span, .. } | Expr::And { span, .. } | Expr::Or { span, .. }
+            | Expr::Do { span, .. } => span,
+            Expr::Var(_, span) | Expr::Quote(_, span) | Expr::Begin(_, span) | Expr::Int(_, span) | Expr::Num(_, span)
+            | Expr::Bool(_, span) | Expr::Char(_, span) | Expr::Str(_, span) | Expr::Nil(span) => span,
+        }
+    }
+
+    pub fn to_datum(&self) -> Value {
+        let form = |keyword: &str, rest: Vec<Value>| Value::list([vec![Value::symbol(keyword)], rest].concat());
+        let binds = |binds: &[(String, Expr)]| Value::list(binds.iter().map(|(ident, expr)| Value::list(vec![Value::symbol(ident), expr.to_datum()])).collect());
+        let clauses = |cond_then: &[(Expr, Expr)]| cond_then.iter().map(|(cond, then)| Value::list(vec![cond.to_datum(), then.to_datum()])).collect::<Vec<_>>();
+        match self {
+            Expr::Apply { proc, args, .. } => Value::list([vec![proc.to_datum()], args.iter().map(Expr::to_datum).collect()].concat()),
+            Expr::Lambda { params, body, .. } => form("lambda", [vec![Value::list(params.iter().map(|param| Value::symbol(param)).collect())], body.to_data()].concat()),
+            Expr::Let { binds: bs, body, .. } => form("let", [vec![binds(bs)], body.to_data()].concat()),
+            Expr::LetStar { binds: bs, body, .. } => form("let*", [vec![binds(bs)], body.to_data()].concat()),
+            Expr::LetRec { binds: bs, body, .. } => form("letrec", [vec![binds(bs)], body.to_data()].concat()),
+            Expr::Set { ident, expr, .. } => form("set!", vec![Value::symbol(ident), expr.to_datum()]),
+            Expr::Var(ident, _) => Value::symbol(ident),
+            Expr::Quote(datum, _) => form("quote", vec![(**datum).clone()]),
+            Expr::Begin(exprs, _) => form("begin", exprs.iter().map(Expr::to_datum).collect()),
+            Expr::If { cond, expr1, expr2, .. } => form("if", vec![cond.to_datum(), expr1.to_datum(), expr2.to_datum()]),
+            Expr::Cond { cond_then, .. } => form("cond", clauses(cond_then)),
+            Expr::Guard { var, cond_then, body, .. } => form("guard", [vec![Value::list([vec![Value::symbol(var)], clauses(cond_then)].concat())], body.to_data()].concat()),
+            Expr::And { args, .. } => form("and", args.iter().map(Expr::to_datum).collect()),
+            Expr::Or { args, .. } => form("or", args.iter().map(Expr::to_datum).collect()),
+            Expr::Do { binds, test, exprs, body, .. } => {
+                let binds = binds.iter().map(|(ident, init, update)| Value::list(vec![Value::symbol(ident), init.to_datum(), update.to_datum()])).collect();
+                let exit = [vec![test.to_datum()], exprs.iter().map(Expr::to_datum).collect()].concat();
+                form("do", vec![Value::list(binds), Value::list(exit), body.to_datum()])
+            },
+            Expr::Int(val, _) => Int(*val),
+            Expr::Num(val, _) => Value::Num(*val),
+            Expr::Bool(val, _) => Value::Bool(*val),
+            Expr::Char(val, _) => Value::Char(*val),
+            Expr::Str(val, _) => Value::literal_str(val.clone()),
+            Expr::Nil(_) => form("quote", vec![Value::Nil]),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Value {
-    Pair(Rc<RefCell<(Value, Value)>>),
+    Pair(Rc<RefCell<Cons>>),
     Proc(Proc),
     Symbol(String),
-    Num(f32),
+    Int(i64),
+    Num(f64),
     Bool(bool),
-    Str(Rc<String>),
+    Char(char),
+    Str(Rc<StrCell>),
+    Bytevector(Rc<RefCell<Vec<u8>>>),
+    Values(Vec<Value>),
+    Port(Port),
+    ErrorObject(Rc<Error>),
+    Eof,
+    // what output procedures and mutators return, neither echoed nor bound by the repl
+    Unspecified,
     Nil,
 }
 
+// the car and cdr of a pair
+#[derive(Clone)]
+pub struct Cons(pub Value, pub Value);
+
+// a long list dropped one pair inside the next would overflow the stack, so its pairs are unlinked in a loop
+impl Drop for Cons {
+    fn drop(&mut self) {
+        let mut rest = std::mem::replace(&mut self.1, Nil);
+        while let Pair(pair) = &mut rest {
+            let Some(pair) = Rc::get_mut(pair) else {
+                break;
+            };
+            let next = std::mem::replace(&mut pair.get_mut().1, Nil);
+            rest = next;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StrCell {
+    // kept as characters so that indexing and mutation need not walk the string
+    chars: RefCell<Vec<char>>,
+    mutable: bool,
+}
+
+impl StrCell {
+    pub fn borrow(&self) -> Ref<'_, Vec<char>> {
+        self.chars.borrow()
+    }
+
+    pub fn string(&self) -> String {
+        self.chars.borrow().iter().collect()
+    }
+
+    pub fn modify<T>(&self, f: impl FnOnce(&mut Vec<char>) -> Result<T, Error>) -> Result<T, Error> {
+        f(&mut self.chars.borrow_mut())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Proc {
-    Lambda { env: Env, params: Vec<String>, body: Body },
+    Lambda { name: Option<String>, env: Env, params: Vec<String>, body: Body },
     Opr(&'static str),
 }
 
+impl Proc {
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Proc::Lambda { name, .. } => name.as_deref(),
+            Proc::Opr(opr) => Some(opr),
+        }
+    }
+}
+
+// the external representation, as printed by `write`
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Pair(pair) => write!(f, "({} . {})", pair.borrow().0, pair.borrow().1),
-            Proc(Proc::Lambda { env, params, body }) => {
-                write!(f, "env\n")?;
-                write!(f, "{}", env)?;
-                write!(f, "{:?} -> {:?}", params, body)
+        f.write_str(&Printer::write().print(self))
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Printer::write().print(self))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Labels {
+    Cycles,
+    Shared,
+    None,
+}
+
+pub struct Printer {
+    write: bool,
+    labels: Labels,
+}
+
+impl Printer {
+    pub fn write() -> Self {
+        Printer { write: true, labels: Labels::Cycles }
+    }
+
+    pub fn write_shared() -> Self {
+        Printer { write: true, labels: Labels::Shared }
+    }
+
+    pub fn write_simple() -> Self {
+        Printer { write: true, labels: Labels::None }
+    }
+
+    pub fn display() -> Self {
+        Printer { write: false, labels: Labels::Cycles }
+    }
+
+    pub fn print(&self, value: &Value) -> String {
+        let mut labeled = HashSet::new();
+        if self.labels != Labels::None {
+            self.find_labels(value, &mut HashSet::new(), &mut HashSet::new(), &mut labeled);
+        }
+        let mut labels = labeled.into_iter().map(|id| (id, None)).collect();
+        let mut out = String::new();
+        self.print_value(value, &mut labels, &mut out);
+        out
+    }
+
+    pub fn has_labels(&self, value: &Value) -> bool {
+        let mut labeled = HashSet::new();
+        self.find_labels(value, &mut HashSet::new(), &mut HashSet::new(), &mut labeled);
+        !labeled.is_empty()
+    }
+
+    // collects the pairs that need a `#n=` label: those reached again while still being
+    // printed (cycles), or reached more than once at all when printing shared structure
+    fn find_labels(&self, value: &Value, visiting: &mut HashSet<usize>, seen: &mut HashSet<usize>, labeled: &mut HashSet<usize>) {
+        if let Values(values) = value {
+            for value in values {
+                self.find_labels(value, visiting, seen, labeled);
+            }
+            return;
+        }
+
+        let mut chain = Vec::new();
+        let mut rest = value.clone();
+        while let Pair(pair) = rest {
+            let id = Rc::as_ptr(&pair) as usize;
+            if visiting.contains(&id) || (self.labels == Labels::Shared && seen.contains(&id)) {
+                labeled.insert(id);
+                break;
+            }
+            if !seen.insert(id) {
+                break;
+            }
+            visiting.insert(id);
+            chain.push(id);
+            self.find_labels(&pair.borrow().0, visiting, seen, labeled);
+            rest = pair.borrow().1.clone();
+        }
+        for id in chain {
+            visiting.remove(&id);
+        }
+    }
+
+    // prints `#n#` for a labeled pair printed before, or `#n=` in front of its first occurrence
+    fn print_label(&self, pair: &Rc<RefCell<Cons>>, labels: &mut HashMap<usize, Option<usize>>, out: &mut String) -> bool {
+        let id = Rc::as_ptr(pair) as usize;
+        let count = labels.values().filter(|label| label.is_some()).count();
+        match labels.get_mut(&id) {
+            Some(Some(label)) => {
+                out.push_str(&format!("#{}#", label));
+                true
+            },
+            Some(label) => {
+                *label = Some(count);
+                out.push_str(&format!("#{}=", count));
+                false
             },
-            Proc(Proc::Opr(opr)) => write!(f, "{:?}", opr),
-            Symbol(symbol) => symbol.fmt(f),
-            Value::Num(val) => val.fmt(f),
-            Value::Bool(val) => if *val { "#t" } else { "#f" }.fmt(f),
-            Value::Str(val) => write!(f, "\"{}\"", val),
-            Value::Nil => "()".fmt(f),
+            None => false,
+        }
+    }
+
+    fn print_value(&self, value: &Value, labels: &mut HashMap<usize, Option<usize>>, out: &mut String) {
+        match value {
+            Pair(pair) => {
+                if self.print_label(pair, labels, out) {
+                    return;
+                }
+                out.push('(');
+                self.print_value(&pair.borrow().0, labels, out);
+                let mut rest = pair.borrow().1.clone();
+                loop {
+                    rest = match rest {
+                        Pair(pair) if !labels.contains_key(&(Rc::as_ptr(&pair) as usize)) => {
+                            out.push(' ');
+                            self.print_value(&pair.borrow().0, labels, out);
+                            let next = pair.borrow().1.clone();
+                            next
+                        },
+                        Value::Nil => break,
+                        tail => {
+                            out.push_str(" . ");
+                            self.print_value(&tail, labels, out);
+                            break;
+                        },
+                    };
+                }
+                out.push(')');
+            },
+            Proc(proc) => match proc.name() {
+                Some(name) => out.push_str(&format!("#<procedure {}>", name)),
+                None => out.push_str("#<procedure>"),
+            },
+            Symbol(symbol) => out.push_str(symbol),
+            Int(val) => out.push_str(&val.to_string()),
+            Value::Num(val) => out.push_str(&format_flonum(*val)),
+            Value::Bool(val) => out.push_str(if *val { "#t" } else { "#f" }),
+            Value::Char(c) if !self.write => out.push(*c),
+            Value::Char(c) => match char_name(*c) {
+                Some(name) => out.push_str(&format!("#\\{}", name)),
+                None => out.push_str(&format!("#\\{}", c)),
+            },
+            Value::Str(string) if !self.write => out.extend(string.borrow().iter()),
+            Value::Str(string) => {
+                out.push('"');
+                for &c in string.borrow().iter() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\t' => out.push_str("\\t"),
+                        '\r' => out.push_str("\\r"),
+                        c if c.is_control() => out.push_str(&format!("\\x{:x};", c as u32)),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            },
+            Values(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    self.print_value(value, labels, out);
+                }
+            },
+            Bytevector(bytes) => {
+                let bytes: Vec<String> = bytes.borrow().iter().map(|byte| byte.to_string()).collect();
+                out.push_str(&format!("#u8({})", bytes.join(" ")));
+            },
+            Port(port) => out.push_str(&format!("{:?}", port)),
+            ErrorObject(err) => out.push_str(&format!("#<error {}>", err.message())),
+            Eof => out.push_str("#<eof>"),
+            Unspecified => out.push_str("#<unspecified>"),
+            Value::Nil => out.push_str("()"),
         }
     }
 }
@@ -105,58 +479,311 @@ impl PartialEq for Value {
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
-            (Value::Num(lhs), Value::Num(rhs)) => f32::partial_cmp(lhs, rhs),
+            (Int(lhs), Int(rhs)) => i64::partial_cmp(lhs, rhs),
+            (Int(_) | Value::Num(_), Int(_) | Value::Num(_)) => f64::partial_cmp(&self.to_f64().ok()?, &other.to_f64().ok()?),
             (Value::Bool(lhs), Value::Bool(rhs)) => bool::partial_cmp(lhs, rhs),
-            (Value::Str(lhs), Value::Str(rhs)) => str::partial_cmp(lhs.as_str(), rhs.as_str()),
+            (Value::Char(lhs), Value::Char(rhs)) => char::partial_cmp(lhs, rhs),
+            (Value::Str(lhs), Value::Str(rhs)) => lhs.borrow().partial_cmp(&*rhs.borrow()),
             _ => None,
         }
     }
 }
 
 impl Add for Value {
-    type Output = Result<Self, String>;
+    type Output = Result<Self, Error>;
 
     fn add(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (Value::Num(lhs), Value::Num(rhs)) => Ok(Value::Num(lhs + rhs)),
-            (Value::Num(_), rhs) => Err(format!("{:?} is not a number", rhs)),
-            lhs => Err(format!("{:?} is not a number", lhs)),
-        }
+        Value::arith(&self, &other, i64::checked_add, |lhs, rhs| lhs + rhs)
     }
 }
 
 impl Sub for Value {
-    type Output = Result<Self, String>;
+    type Output = Result<Self, Error>;
 
     fn sub(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (Value::Num(lhs), Value::Num(rhs)) => Ok(Value::Num(lhs - rhs)),
-            (Value::Num(_), rhs) => Err(format!("{:?} is not a number", rhs)),
-            lhs => Err(format!("{:?} is not a number", lhs)),
-        }
+        Value::arith(&self, &other, i64::checked_sub, |lhs, rhs| lhs - rhs)
     }
 }
 
 impl Mul for Value {
-    type Output = Result<Self, String>;
+    type Output = Result<Self, Error>;
 
     fn mul(self, other: Self) -> Self::Output {
-        match (self, other) {
-            (Value::Num(lhs), Value::Num(rhs)) => Ok(Value::Num(lhs * rhs)),
-            (Value::Num(_), rhs) => Err(format!("{:?} is not a number", rhs)),
-            lhs => Err(format!("{:?} is not a number", lhs)),
-        }
+        Value::arith(&self, &other, i64::checked_mul, |lhs, rhs| lhs * rhs)
     }
 }
 
 impl Div for Value {
-    type Output = Result<Self, String>;
+    type Output = Result<Self, Error>;
 
     fn div(self, other: Self) -> Self::Output {
         match (self, other) {
-            (Value::Num(lhs), Value::Num(rhs)) => Ok(Value::Num(lhs / rhs)),
-            (Value::Num(_), rhs) => Err(format!("{:?} is not a number", rhs)),
-            lhs => Err(format!("{:?} is not a number", lhs)),
+            (Int(_), Int(0)) => Err(Error::range("division by zero")),
+            (Int(lhs), Int(rhs)) if lhs.checked_rem(rhs) == Some(0) => Ok(lhs.checked_div(rhs).map_or(Value::Num(lhs as f64 / rhs as f64), Int)),
+            (lhs, rhs) => Ok(Value::Num(lhs.to_f64()? / rhs.to_f64()?)),
+        }
+    }
+}
+
+impl Value {
+    pub fn to_f64(&self) -> Result<f64, Error> {
+        match self {
+            Int(val) => Ok(*val as f64),
+            Value::Num(val) => Ok(*val),
+            _ => Err(Error::expected("number", self)),
+        }
+    }
+
+    fn to_integer(&self) -> Result<f64, Error> {
+        match self.to_f64()? {
+            val if val.fract() == 0.0 => Ok(val),
+            _ => Err(Error::expected("integer", self)),
+        }
+    }
+
+    // exact when both operands are exact and the result fits, inexact otherwise
+    fn arith(lhs: &Value, rhs: &Value, int_op: fn(i64, i64) -> Option<i64>, float_op: fn(f64, f64) -> f64) -> Result<Value, Error> {
+        if let (Int(lhs), Int(rhs)) = (lhs, rhs) {
+            if let Some(val) = int_op(*lhs, *rhs) {
+                return Ok(Int(val));
+            }
+        }
+        Ok(Value::Num(float_op(lhs.to_f64()?, rhs.to_f64()?)))
+    }
+
+    fn map_float(&self, f: fn(f64) -> f64) -> Result<Value, Error> {
+        match self {
+            Int(val) => Ok(Int(*val)),
+            _ => Ok(Value::Num(f(self.to_f64()?))),
+        }
+    }
+
+    pub fn is_exact(&self) -> Result<bool, Error> {
+        match self {
+            Int(_) => Ok(true),
+            Value::Num(_) => Ok(false),
+            _ => Err(Error::expected("number", self)),
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Int(_) => true,
+            Value::Num(val) => val.fract() == 0.0,
+            _ => false,
+        }
+    }
+
+    pub fn exact(&self) -> Result<Value, Error> {
+        match self {
+            Int(val) => Ok(Int(*val)),
+            Value::Num(val) if val.fract() == 0.0 && val.abs() < i64::MAX as f64 => Ok(Int(*val as i64)),
+            Value::Num(_) => Err(Error::range(format!("{:?} has no exact representation", self))),
+            _ => Err(Error::expected("number", self)),
+        }
+    }
+
+    pub fn inexact(&self) -> Result<Value, Error> {
+        Ok(Value::Num(self.to_f64()?))
+    }
+
+    pub fn abs(&self) -> Result<Value, Error> {
+        match self {
+            Int(val) if *val != i64::MIN => Ok(Int(val.abs())),
+            _ => Ok(Value::Num(self.to_f64()?.abs())),
+        }
+    }
+
+    pub fn negate(&self) -> Result<Value, Error> {
+        Int(0) - self.clone()
+    }
+
+    // (quotient, remainder) rounding the quotient toward zero, or toward negative infinity when `floor`
+    pub fn integer_div(lhs: &Value, rhs: &Value, floor: bool) -> Result<(Value, Value), Error> {
+        match (lhs, rhs) {
+            (Int(_), Int(0)) => Err(Error::range("division by zero")),
+            (Int(lhs), Int(rhs)) => {
+                let rem = lhs.wrapping_rem(*rhs);
+                let Some(quo) = lhs.checked_div(*rhs) else {
+                    // only i64::MIN by -1 overflows, leaving the quotient 2^63 inexact and the remainder 0
+                    return Ok((Value::Num(-(*lhs as f64)), Int(rem)));
+                };
+                if floor && rem != 0 && (rem < 0) != (*rhs < 0) {
+                    Ok((Int(quo - 1), Int(rem + rhs)))
+                } else {
+                    Ok((Int(quo), Int(rem)))
+                }
+            },
+            _ => {
+                let (lhs, rhs) = (lhs.to_integer()?, rhs.to_integer()?);
+                if rhs == 0.0 {
+                    return Err(Error::range("division by zero"));
+                }
+                let quo = if floor { (lhs / rhs).floor() } else { (lhs / rhs).trunc() };
+                Ok((Value::Num(quo), Value::Num(lhs - quo * rhs)))
+            },
+        }
+    }
+
+    pub fn gcd(values: &[Value]) -> Result<Value, Error> {
+        // folded over magnitudes, so that only a final result of 2^63 is inexact
+        let exact = values.iter().try_fold(0, |acc, value| match value {
+            Int(val) => Some(gcd_int(acc, val.unsigned_abs())),
+            _ => None,
+        });
+        match exact.map(i64::try_from) {
+            Some(Ok(gcd)) => Ok(Int(gcd)),
+            _ => Self::integer_fold(values, 0, |_, _| None, gcd_float),
+        }
+    }
+
+    pub fn lcm(values: &[Value]) -> Result<Value, Error> {
+        Self::integer_fold(
+            values, 1,
+            |lhs, rhs| if rhs == 0 { Some(0) } else { (lhs / i64::try_from(gcd_int(lhs.unsigned_abs(), rhs.unsigned_abs())).ok()?).checked_mul(rhs.checked_abs()?) },
+            |lhs, rhs| if rhs == 0.0 { 0.0 } else { (lhs / gcd_float(lhs, rhs) * rhs).abs() },
+        )
+    }
+
+    // folds integers exactly while every argument is exact and the result fits
+    fn integer_fold(values: &[Value], init: i64, int_op: fn(i64, i64) -> Option<i64>, float_op: fn(f64, f64) -> f64) -> Result<Value, Error> {
+        let mut acc = Some(init);
+        let mut float_acc = init as f64;
+        for value in values {
+            acc = match (acc, value) {
+                (Some(acc), Int(val)) => int_op(acc, *val),
+                _ => None,
+            };
+            float_acc = float_op(float_acc, value.to_integer()?);
+        }
+        match acc {
+            Some(acc) => Ok(Int(acc)),
+            None => Ok(Value::Num(float_acc)),
+        }
+    }
+
+    pub fn min_max(values: &[Value], max: bool) -> Result<Value, Error> {
+        let mut values = values.iter();
+        let Some(first) = values.next() else {
+            return Err(Error::new(ErrorKind::Arity { expected: None, actual: 0 }));
+        };
+        let mut exact = first.is_exact()?;
+        let mut acc = first.clone();
+        for value in values {
+            exact &= value.is_exact()?;
+            let ord = value.partial_cmp(&acc).ok_or_else(|| Error::expected("real number", value))?;
+            if (max && ord.is_gt()) || (!max && ord.is_lt()) {
+                acc = value.clone();
+            }
+        }
+        if exact { Ok(acc) } else { acc.inexact() }
+    }
+
+    pub fn floor(&self) -> Result<Value, Error> {
+        self.map_float(f64::floor)
+    }
+
+    pub fn ceiling(&self) -> Result<Value, Error> {
+        self.map_float(f64::ceil)
+    }
+
+    pub fn round(&self) -> Result<Value, Error> {
+        self.map_float(f64::round_ties_even)
+    }
+
+    pub fn truncate(&self) -> Result<Value, Error> {
+        self.map_float(f64::trunc)
+    }
+
+    pub fn sqrt(&self) -> Result<Value, Error> {
+        if let Int(val) = self {
+            if let Ok(Values(values)) = self.exact_integer_sqrt() {
+                if values[1] == Int(0) {
+                    return Ok(values[0].clone());
+                }
+            }
+            if *val < 0 {
+                return Err(Error::range("complex numbers are unsupported"));
+            }
+        }
+        match self.to_f64()? {
+            val if val < 0.0 => Err(Error::range("complex numbers are unsupported")),
+            val => Ok(Value::Num(val.sqrt())),
+        }
+    }
+
+    pub fn exact_integer_sqrt(&self) -> Result<Value, Error> {
+        match self {
+            Int(val) if *val >= 0 => {
+                let mut root = (*val as f64).sqrt() as i64;
+                while root.checked_mul(root).is_none_or(|square| square > *val) {
+                    root -= 1;
+                }
+                while (root + 1).checked_mul(root + 1).is_some_and(|square| square <= *val) {
+                    root += 1;
+                }
+                Ok(Values(vec![Int(root), Int(val - root * root)]))
+            },
+            _ => Err(Error::expected("non-negative exact integer", self)),
+        }
+    }
+
+    pub fn expt(base: &Value, exponent: &Value) -> Result<Value, Error> {
+        if let (Int(base), Int(exponent)) = (base, exponent) {
+            if let Some(val) = u32::try_from(*exponent).ok().and_then(|exponent| base.checked_pow(exponent)) {
+                return Ok(Int(val));
+            }
+        }
+        Ok(Value::Num(base.to_f64()?.powf(exponent.to_f64()?)))
+    }
+
+    pub fn log(&self, base: Option<&Value>) -> Result<Value, Error> {
+        match base {
+            Some(base) => Ok(Value::Num(self.to_f64()?.ln() / base.to_f64()?.ln())),
+            None => Ok(Value::Num(self.to_f64()?.ln())),
+        }
+    }
+
+    pub fn atan(&self, x: Option<&Value>) -> Result<Value, Error> {
+        match x {
+            Some(x) => Ok(Value::Num(self.to_f64()?.atan2(x.to_f64()?))),
+            None => Ok(Value::Num(self.to_f64()?.atan())),
+        }
+    }
+
+    pub fn transcendental(&self, f: fn(f64) -> f64) -> Result<Value, Error> {
+        Ok(Value::Num(f(self.to_f64()?)))
+    }
+
+    pub fn numerator(&self) -> Result<Value, Error> {
+        match self {
+            Int(val) => Ok(Int(*val)),
+            _ => Ok(Value::Num(self.to_f64()? * self.denominator()?.to_f64()?)),
+        }
+    }
+
+    pub fn denominator(&self) -> Result<Value, Error> {
+        match self {
+            Int(_) => Ok(Int(1)),
+            _ => {
+                let val = self.to_f64()?;
+                let mut denominator = 1.0;
+                while (val * denominator).fract() != 0.0 && denominator < f64::MAX {
+                    denominator *= 2.0;
+                }
+                Ok(Value::Num(denominator))
+            },
+        }
+    }
+
+    pub fn sign(&self) -> Result<Ordering, Error> {
+        self.partial_cmp(&Int(0)).ok_or_else(|| Error::expected("number", self))
+    }
+
+    pub fn is_odd(&self) -> Result<bool, Error> {
+        match self {
+            Int(val) => Ok(val % 2 != 0),
+            _ => Ok(self.to_integer()? % 2.0 != 0.0),
         }
     }
 }
@@ -167,52 +794,100 @@ impl Value {
             (Pair(lhs), Pair(rhs)) => Rc::ptr_eq(lhs, rhs),
             (Proc(_), Proc(_)) => false,
             (Symbol(lhs)     , Symbol(rhs)     ) => lhs == rhs,
+            (Int(lhs)        , Int(rhs)        ) => lhs == rhs,
             (Value::Num(lhs) , Value::Num(rhs) ) => lhs == rhs,
             (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+            (Value::Char(lhs), Value::Char(rhs)) => lhs == rhs,
             (Value::Str(lhs) , Value::Str(rhs) ) => Rc::ptr_eq(lhs, rhs),
+            (Bytevector(lhs) , Bytevector(rhs) ) => Rc::ptr_eq(lhs, rhs),
+            (Port(lhs)       , Port(rhs)       ) => Port::ptr_eq(lhs, rhs),
+            (ErrorObject(lhs), ErrorObject(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Eof             , Eof             ) => true,
+            (Unspecified     , Unspecified     ) => true,
             (Value::Nil      , Value::Nil      ) => true,
             _ => false,
         }
     }
 
     pub fn equal(lhs: &Value, rhs: &Value) -> bool {
+        Self::equal_pairs(lhs, rhs, &mut HashSet::new())
+    }
+
+    // pairs compared before are assumed equal, so that comparing cyclic structure terminates
+    fn equal_pairs(lhs: &Value, rhs: &Value, assumed: &mut HashSet<(usize, usize)>) -> bool {
+        let (mut lhs, mut rhs) = (lhs.clone(), rhs.clone());
+        loop {
+            let (Pair(lpair), Pair(rpair)) = (&lhs, &rhs) else {
+                return Self::equal_atoms(&lhs, &rhs);
+            };
+            if !assumed.insert((Rc::as_ptr(lpair) as usize, Rc::as_ptr(rpair) as usize)) {
+                return true;
+            }
+            if !Self::equal_pairs(&lpair.borrow().0, &rpair.borrow().0, assumed) {
+                return false;
+            }
+            let next = (lpair.borrow().1.clone(), rpair.borrow().1.clone());
+            (lhs, rhs) = next;
+        }
+    }
+
+    fn equal_atoms(lhs: &Value, rhs: &Value) -> bool {
         match (lhs, rhs) {
-            (Pair(lhs), Pair(rhs)) => Self::equal(&lhs.borrow().0, &rhs.borrow().0) && Self::equal(&lhs.borrow().1, &rhs.borrow().1),
+            (Values(lhs), Values(rhs)) => lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(lhs, rhs)| Self::equal(lhs, rhs)),
             (Proc(_), Proc(_)) => false,
             (Symbol(lhs)     , Symbol(rhs)     ) => lhs == rhs,
+            (Int(lhs)        , Int(rhs)        ) => lhs == rhs,
             (Value::Num(lhs) , Value::Num(rhs) ) => lhs == rhs,
             (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
-            (Value::Str(lhs) , Value::Str(rhs) ) => lhs == rhs,
+            (Value::Char(lhs), Value::Char(rhs)) => lhs == rhs,
+            (Value::Str(lhs) , Value::Str(rhs) ) => *lhs.borrow() == *rhs.borrow(),
+            (Bytevector(lhs) , Bytevector(rhs) ) => *lhs.borrow() == *rhs.borrow(),
+            (Port(lhs)       , Port(rhs)       ) => Port::ptr_eq(lhs, rhs),
+            (ErrorObject(lhs), ErrorObject(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (Eof             , Eof             ) => true,
+            (Unspecified     , Unspecified     ) => true,
             (Value::Nil      , Value::Nil      ) => true,
             _ => false,
         }
     }
 
+    // a lambda bound by `define` takes the name it is bound to
+    pub fn named(self, ident: &str) -> Value {
+        match self {
+            Proc(Proc::Lambda { name: None, env, params, body }) => Proc(Proc::Lambda { name: Some(ident.to_string()), env, params, body }),
+            value => value,
+        }
+    }
+
+    pub fn str(string: String) -> Value {
+        Value::Str(Rc::new(StrCell { chars: RefCell::new(string.chars().collect()), mutable: true }))
+    }
+
+    pub fn literal_str(string: String) -> Value {
+        Value::Str(Rc::new(StrCell { chars: RefCell::new(string.chars().collect()), mutable: false }))
+    }
+
+    pub fn symbol(name: &str) -> Value {
+        Symbol(name.to_string())
+    }
+
     pub fn list(values: Vec<Value>) -> Value {
         let mut list = Value::Nil;
         for value in values.into_iter().rev() {
-            list = Pair(Rc::new(RefCell::new((value, list))));
+            list = Pair(Rc::new(RefCell::new(Cons(value, list))));
         }
         list
     }
 
     pub fn is_list(&self) -> bool {
-        match self {
-            Pair(pair) => pair.borrow().1.is_list(),
-            Value::Nil => true,
-            _ => false,
-        }
+        self.length().is_ok()
     }
-    
-    pub fn length(&self) -> Result<u32, String> {
-        match self {
-            Pair(pair) => Ok(1 + pair.borrow().1.length()?),
-            Value::Nil => Ok(0),
-            _ => Err(String::from("not list")),
-        }
+
+    pub fn length(&self) -> Result<u32, Error> {
+        Ok(self.to_vec()?.len() as u32)
     }
 
-    pub fn last(&self) -> Result<Value, String> {
+    pub fn last(&self) -> Result<Value, Error> {
         match self {
             Pair(pair) => {
                 if let Pair(_) = pair.borrow().1 {
@@ -221,7 +896,7 @@ impl Value {
                     Ok(pair.borrow().0.clone())
                 }
             },
-            _ => Err(String::from("not pair")),
+            _ => Err(Error::expected("pair", self)),
         }
     }
     
@@ -238,64 +913,809 @@ impl Value {
         }
     }
     
-    pub fn append(list1: &Value, list2: &Value) -> Result<Value, String> {
+    pub fn append(list1: &Value, list2: &Value) -> Result<Value, Error> {
         match list1 {
-            Pair(pair) => Ok(Pair(Rc::new(RefCell::new((pair.borrow().0.clone(), Self::append(&pair.borrow().1, list2)?))))),
+            Pair(pair) => Ok(Pair(Rc::new(RefCell::new(Cons(pair.borrow().0.clone(), Self::append(&pair.borrow().1, list2)?))))),
             Value::Nil => Ok(list2.clone()),
-            _ => Err(String::from("not list")),
+            _ => Err(Error::expected("list", list1)),
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Bool(false))
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<Value>, Error> {
+        let mut vec = Vec::new();
+        let mut list = self.clone();
+        // advances two pairs for each one `list` advances, meeting it only on a circular list
+        let mut runner = self.clone();
+        loop {
+            list = match list {
+                Pair(pair) => {
+                    vec.push(pair.borrow().0.clone());
+                    let rest = pair.borrow().1.clone();
+                    rest
+                },
+                Value::Nil => return Ok(vec),
+                _ => return Err(Error::expected("list", self)),
+            };
+            for _ in 0..2 {
+                runner = match runner {
+                    Pair(pair) => pair.borrow().1.clone(),
+                    runner => runner,
+                };
+            }
+            if let (Pair(lhs), Pair(rhs)) = (&list, &runner) {
+                if Rc::ptr_eq(lhs, rhs) {
+                    return Err(Error::expected("finite list", self));
+                }
+            }
+        }
+    }
+
+    pub fn to_index(&self) -> Result<usize, Error> {
+        match self {
+            Int(val) if *val >= 0 => Ok(*val as usize),
+            _ => Err(Error::expected("index", self)),
         }
     }
 
-    pub fn string_append(values: Vec<Value>) -> Result<Value, String> {
+    // rows of the i-th elements of each list, as long as the shortest list
+    fn transpose(lists: &[Value]) -> Result<Vec<Vec<Value>>, Error> {
+        let lists = lists.iter().map(|list| list.to_vec()).collect::<Result<Vec<_>, _>>()?;
+        let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+        Ok((0..len).map(|i| lists.iter().map(|list| list[i].clone()).collect()).collect())
+    }
+
+    pub fn fold(lists: &[Value], knil: Value, mut kons: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut acc = knil;
+        for mut row in Self::transpose(lists)? {
+            row.push(acc);
+            acc = kons(row)?;
+        }
+        Ok(acc)
+    }
+
+    pub fn fold_right(lists: &[Value], knil: Value, mut kons: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut acc = knil;
+        for mut row in Self::transpose(lists)?.into_iter().rev() {
+            row.push(acc);
+            acc = kons(row)?;
+        }
+        Ok(acc)
+    }
+
+    pub fn reduce(list: &Value, ridentity: Value, mut f: impl FnMut(Value, Value) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut elems = list.to_vec()?.into_iter();
+        let Some(mut acc) = elems.next() else {
+            return Ok(ridentity);
+        };
+        for elem in elems {
+            acc = f(elem, acc)?;
+        }
+        Ok(acc)
+    }
+
+    pub fn partition(list: &Value, mut pred: impl FnMut(Value) -> Result<bool, Error>) -> Result<(Value, Value), Error> {
+        let mut ins = Vec::new();
+        let mut outs = Vec::new();
+        for elem in list.to_vec()? {
+            if pred(elem.clone())? {
+                ins.push(elem);
+            } else {
+                outs.push(elem);
+            }
+        }
+        Ok((Self::list(ins), Self::list(outs)))
+    }
+
+    pub fn delete_duplicates(list: &Value, mut eq: impl FnMut(Value, Value) -> Result<bool, Error>) -> Result<Value, Error> {
+        let mut uniq: Vec<Value> = Vec::new();
+        for elem in list.to_vec()? {
+            let mut dup = false;
+            for prev in &uniq {
+                if eq(prev.clone(), elem.clone())? {
+                    dup = true;
+                    break;
+                }
+            }
+            if !dup {
+                uniq.push(elem);
+            }
+        }
+        Ok(Self::list(uniq))
+    }
+
+    pub fn iota(count: &Value, start: &Value, step: &Value) -> Result<Value, Error> {
+        let values = (0..count.to_index()?).map(|i| start.clone() + (Int(i as i64) * step.clone())?).collect::<Result<_, _>>()?;
+        Ok(Self::list(values))
+    }
+
+    pub fn take(list: &Value, k: &Value) -> Result<Value, Error> {
+        let mut elems = Vec::new();
+        let mut list = list.clone();
+        for _ in 0..k.to_index()? {
+            list = match list {
+                Pair(pair) => {
+                    elems.push(pair.borrow().0.clone());
+                    let rest = pair.borrow().1.clone();
+                    rest
+                },
+                _ => return Err(Error::range("list is too short")),
+            };
+        }
+        Ok(Self::list(elems))
+    }
+
+    pub fn drop(list: &Value, k: &Value) -> Result<Value, Error> {
+        let mut list = list.clone();
+        for _ in 0..k.to_index()? {
+            list = match list {
+                Pair(pair) => pair.borrow().1.clone(),
+                _ => return Err(Error::range("list is too short")),
+            };
+        }
+        Ok(list)
+    }
+
+    pub fn append_map(lists: &[Value], mut f: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut results = Vec::new();
+        for row in Self::transpose(lists)? {
+            results.push(f(row)?);
+        }
+        results.into_iter().rev().try_fold(Value::Nil, |tail, list| Self::append(&list, &tail))
+    }
+
+    pub fn filter_map(lists: &[Value], mut f: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut results = Vec::new();
+        for row in Self::transpose(lists)? {
+            let value = f(row)?;
+            if value.is_true() {
+                results.push(value);
+            }
+        }
+        Ok(Self::list(results))
+    }
+
+    pub fn find_tail(list: &Value, mut pred: impl FnMut(Value) -> Result<bool, Error>) -> Result<Value, Error> {
+        let mut list = list.clone();
+        loop {
+            list = match list {
+                Pair(pair) => {
+                    // not borrowed while `pred` runs, since it may mutate the list
+                    let elem = pair.borrow().0.clone();
+                    if pred(elem)? {
+                        return Ok(Pair(pair));
+                    }
+                    let rest = pair.borrow().1.clone();
+                    rest
+                },
+                Value::Nil => return Ok(Value::Bool(false)),
+                rest => return Err(Error::expected("list", &rest)),
+            };
+        }
+    }
+
+    pub fn any(lists: &[Value], mut pred: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        for row in Self::transpose(lists)? {
+            let value = pred(row)?;
+            if value.is_true() {
+                return Ok(value);
+            }
+        }
+        Ok(Value::Bool(false))
+    }
+
+    pub fn every(lists: &[Value], mut pred: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut value = Value::Bool(true);
+        for row in Self::transpose(lists)? {
+            value = pred(row)?;
+            if !value.is_true() {
+                return Ok(value);
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn count(lists: &[Value], mut pred: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        let mut count = 0;
+        for row in Self::transpose(lists)? {
+            if pred(row)?.is_true() {
+                count += 1;
+            }
+        }
+        Ok(Int(count))
+    }
+
+    pub fn list_index(lists: &[Value], mut pred: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Value, Error> {
+        for (i, row) in Self::transpose(lists)?.into_iter().enumerate() {
+            if pred(row)?.is_true() {
+                return Ok(Int(i as i64));
+            }
+        }
+        Ok(Value::Bool(false))
+    }
+
+    pub fn last_pair(&self) -> Result<Value, Error> {
+        let Pair(mut last) = self.clone() else {
+            return Err(Error::expected("pair", self));
+        };
+        // advances two pairs for each one `last` advances, meeting it only on a circular list
+        let mut runner = self.clone();
+        loop {
+            let Pair(next) = last.borrow().1.clone() else {
+                return Ok(Pair(last));
+            };
+            last = next;
+            for _ in 0..2 {
+                runner = match runner {
+                    Pair(pair) => pair.borrow().1.clone(),
+                    runner => runner,
+                };
+            }
+            if let Pair(runner) = &runner {
+                if Rc::ptr_eq(&last, runner) {
+                    return Err(Error::expected("finite list", self));
+                }
+            }
+        }
+    }
+
+    pub fn string_append(values: Vec<Value>) -> Result<Value, Error> {
         let mut sum = String::new();
         for value in values {
-            if let Value::Str(string) = value {
-                sum.push_str(&string);
+            if let Value::Str(string) = &value {
+                sum.extend(string.borrow().iter());
             } else {
-                return Err(String::from("not string"));
+                return Err(Error::expected("string", &value));
             }
         }
-        Ok(Value::Str(Rc::new(sum)))
+        Ok(Value::str(sum))
+    }
+
+    pub fn as_chars(&self) -> Result<Ref<'_, Vec<char>>, Error> {
+        if let Value::Str(string) = self {
+            Ok(string.borrow())
+        } else {
+            Err(Error::expected("string", self))
+        }
+    }
+
+    pub fn as_string(&self) -> Result<String, Error> {
+        Ok(self.as_chars()?.iter().collect())
+    }
+
+    pub fn write_string(&self) -> String {
+        Printer::write().print(self)
+    }
+
+    pub fn display_string(&self) -> String {
+        Printer::display().print(self)
+    }
+
+    pub fn as_char(&self) -> Result<char, Error> {
+        if let Value::Char(c) = self {
+            Ok(*c)
+        } else {
+            Err(Error::expected("char", self))
+        }
+    }
+
+    pub fn string_length(&self) -> Result<Value, Error> {
+        Ok(Int(self.as_chars()?.len() as i64))
+    }
+
+    pub fn string_ref(&self, k: &Value) -> Result<Value, Error> {
+        let k = k.to_index()?;
+        match self.as_chars()?.get(k) {
+            Some(&c) => Ok(Value::Char(c)),
+            None => Err(Error::range(format!("index {} is out of range", k))),
+        }
+    }
+
+    pub fn substring(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let string = self.as_string()?;
+        Ok(Value::str(char_slice(&string, start, end)?.iter().collect()))
+    }
+
+    pub fn string_compare(values: &[Value], ci: bool, test: fn(Ordering) -> bool) -> Result<Value, Error> {
+        let strings = values.iter().map(|value| {
+            let string = value.as_string()?;
+            Ok(if ci { string.to_lowercase() } else { string })
+        }).collect::<Result<Vec<_>, Error>>()?;
+        Ok(Value::Bool(strings.windows(2).all(|p| test(p[0].cmp(&p[1])))))
+    }
+
+    pub fn string_to_list(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let string = self.as_string()?;
+        Ok(Self::list(char_slice(&string, start, end)?.into_iter().map(Value::Char).collect()))
+    }
+
+    pub fn list_to_string(&self) -> Result<Value, Error> {
+        let string = self.to_vec()?.iter().map(|c| c.as_char()).collect::<Result<String, _>>()?;
+        Ok(Value::str(string))
+    }
+
+    pub fn make_string(k: &Value, c: Option<&Value>) -> Result<Value, Error> {
+        let c = match c {
+            Some(c) => c.as_char()?,
+            None => ' ',
+        };
+        Ok(Value::str(std::iter::repeat_n(c, k.to_index()?).collect()))
+    }
+
+    // string literals are immutable
+    fn as_mutable_str(&self) -> Result<&StrCell, Error> {
+        match self {
+            Value::Str(string) if string.mutable => Ok(string),
+            Value::Str(_) => Err(Error::expected("mutable string", self)),
+            _ => Err(Error::expected("string", self)),
+        }
     }
 
-    pub fn symbol_to_string(&self) -> Result<Value, String> {
+    pub fn string_set(&self, k: &Value, c: &Value) -> Result<Value, Error> {
+        let (k, c) = (k.to_index()?, c.as_char()?);
+        self.as_mutable_str()?.modify(|chars| match chars.get_mut(k) {
+            Some(slot) => {
+                *slot = c;
+                Ok(())
+            },
+            None => Err(Error::range(format!("index {} is out of range", k))),
+        })?;
+        Ok(Unspecified)
+    }
+
+    pub fn string_fill(&self, c: &Value, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let c = c.as_char()?;
+        let len = char_slice(&self.as_string()?, start, end)?.len();
+        let start = match start {
+            Some(start) => start.to_index()?,
+            None => 0,
+        };
+        self.as_mutable_str()?.modify(|chars| {
+            chars[start..start + len].fill(c);
+            Ok(())
+        })?;
+        Ok(Unspecified)
+    }
+
+    pub fn string_copy_to(&self, at: &Value, from: &Value, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let at = at.to_index()?;
+        let source = char_slice(&from.as_string()?, start, end)?;
+        self.as_mutable_str()?.modify(|chars| {
+            if at + source.len() <= chars.len() {
+                chars[at..at + source.len()].copy_from_slice(&source);
+                Ok(())
+            } else {
+                Err(Error::range(format!("{} characters do not fit at index {}", source.len(), at)))
+            }
+        })?;
+        Ok(Unspecified)
+    }
+
+    pub fn bytevector(bytes: Vec<u8>) -> Value {
+        Bytevector(Rc::new(RefCell::new(bytes)))
+    }
+
+    pub fn borrow_bytes(&self) -> Result<Ref<'_, Vec<u8>>, Error> {
+        if let Bytevector(bytes) = self {
+            Ok(bytes.borrow())
+        } else {
+            Err(Error::expected("bytevector", self))
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.borrow_bytes()?.clone())
+    }
+
+    pub fn to_byte(&self) -> Result<u8, Error> {
+        match self {
+            Int(val) if (0..=255).contains(val) => Ok(*val as u8),
+            _ => Err(Error::expected("byte", self)),
+        }
+    }
+
+    pub fn make_bytevector(k: &Value, fill: Option<&Value>) -> Result<Value, Error> {
+        let fill = match fill {
+            Some(fill) => fill.to_byte()?,
+            None => 0,
+        };
+        Ok(Self::bytevector(vec![fill; k.to_index()?]))
+    }
+
+    pub fn bytevector_u8_ref(&self, k: &Value) -> Result<Value, Error> {
+        let k = k.to_index()?;
+        match self.borrow_bytes()?.get(k) {
+            Some(byte) => Ok(Int(*byte as i64)),
+            None => Err(Error::range(format!("index {} is out of range", k))),
+        }
+    }
+
+    pub fn bytevector_u8_set(&self, k: &Value, byte: &Value) -> Result<Value, Error> {
+        let (k, byte) = (k.to_index()?, byte.to_byte()?);
+        let Bytevector(bytes) = self else {
+            return Err(Error::expected("bytevector", self));
+        };
+        match bytes.borrow_mut().get_mut(k) {
+            Some(slot) => *slot = byte,
+            None => return Err(Error::range(format!("index {} is out of range", k))),
+        }
+        Ok(Unspecified)
+    }
+
+    pub fn bytevector_copy(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let bytes = self.as_bytes()?;
+        Ok(Self::bytevector(bytes[index_range(bytes.len(), start, end)?].to_vec()))
+    }
+
+    pub fn bytevector_copy_to(&self, at: &Value, from: &Value, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let at = at.to_index()?;
+        let source = from.bytevector_copy(start, end)?.as_bytes()?;
+        let Bytevector(bytes) = self else {
+            return Err(Error::expected("bytevector", self));
+        };
+        match bytes.borrow_mut().get_mut(at..at + source.len()) {
+            Some(slice) => slice.copy_from_slice(&source),
+            None => return Err(Error::range(format!("{} bytes do not fit at index {}", source.len(), at))),
+        }
+        Ok(Unspecified)
+    }
+
+    pub fn bytevector_append(values: &[Value]) -> Result<Value, Error> {
+        let mut bytes = Vec::new();
+        for value in values {
+            bytes.extend(value.as_bytes()?);
+        }
+        Ok(Self::bytevector(bytes))
+    }
+
+    pub fn utf8_to_string(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        let bytes = self.bytevector_copy(start, end)?.as_bytes()?;
+        String::from_utf8(bytes).map(Value::str).map_err(|err| Error::range(err.to_string()))
+    }
+
+    pub fn string_to_utf8(&self, start: Option<&Value>, end: Option<&Value>) -> Result<Value, Error> {
+        Ok(Self::bytevector(self.substring(start, end)?.as_string()?.into_bytes()))
+    }
+
+    pub fn string_upcase(&self) -> Result<Value, Error> {
+        Ok(Value::str(self.as_string()?.to_uppercase()))
+    }
+
+    pub fn string_downcase(&self) -> Result<Value, Error> {
+        Ok(Value::str(self.as_string()?.to_lowercase()))
+    }
+
+    pub fn string_contains(&self, pattern: &Value) -> Result<Value, Error> {
+        let string = self.as_string()?;
+        match string.find(&pattern.as_string()?) {
+            Some(i) => Ok(Int(string[..i].chars().count() as i64)),
+            None => Ok(Value::Bool(false)),
+        }
+    }
+
+    // the index of the first character satisfying `pred`
+    pub fn string_index(&self, mut pred: impl FnMut(char) -> Result<bool, Error>) -> Result<Value, Error> {
+        // copied so that `pred` may mutate the string
+        let chars = self.as_chars()?.clone();
+        for (i, c) in chars.into_iter().enumerate() {
+            if pred(c)? {
+                return Ok(Int(i as i64));
+            }
+        }
+        Ok(Value::Bool(false))
+    }
+
+    pub fn string_join(&self, delimiter: Option<&Value>) -> Result<Value, Error> {
+        let strings = self.to_vec()?.iter().map(|string| string.as_string()).collect::<Result<Vec<_>, _>>()?;
+        let delimiter = match delimiter {
+            Some(delimiter) => delimiter.as_string()?,
+            None => String::from(" "),
+        };
+        Ok(Value::str(strings.join(&delimiter)))
+    }
+
+    pub fn string_split(&self, delimiter: Option<&Value>) -> Result<Value, Error> {
+        let string = self.as_string()?;
+        let fields: Vec<&str> = match delimiter {
+            Some(Value::Char(c)) => string.split(*c).collect(),
+            Some(delimiter) => string.split(delimiter.as_string()?.as_str()).collect(),
+            None => string.split_whitespace().collect(),
+        };
+        Ok(Self::list(fields.into_iter().map(|field| Value::str(field.to_string())).collect()))
+    }
+
+    pub fn string_trim(&self, left: bool, right: bool) -> Result<Value, Error> {
+        let string = self.as_string()?;
+        let mut trimmed = string.as_str();
+        if left {
+            trimmed = trimmed.trim_start();
+        }
+        if right {
+            trimmed = trimmed.trim_end();
+        }
+        Ok(Value::str(trimmed.to_string()))
+    }
+
+    pub fn string_pad(&self, n: &Value, c: Option<&Value>, right: bool) -> Result<Value, Error> {
+        let chars: Vec<char> = self.as_string()?.chars().collect();
+        let n = n.to_index()?;
+        let c = match c {
+            Some(c) => c.as_char()?,
+            None => ' ',
+        };
+        let padded: String = if right {
+            chars.iter().copied().chain(std::iter::repeat(c)).take(n).collect()
+        } else if chars.len() >= n {
+            chars[chars.len() - n..].iter().collect()
+        } else {
+            std::iter::repeat_n(c, n - chars.len()).chain(chars.iter().copied()).collect()
+        };
+        Ok(Value::str(padded))
+    }
+
+    pub fn string_map(strings: &[Value], mut f: impl FnMut(Vec<Value>) -> Result<Value, Error>) -> Result<Vec<Value>, Error> {
+        let strings = strings.iter().map(|string| Ok(string.as_string()?.chars().collect())).collect::<Result<Vec<Vec<char>>, Error>>()?;
+        let len = strings.iter().map(|string| string.len()).min().unwrap_or(0);
+        (0..len).map(|i| f(strings.iter().map(|string| Value::Char(string[i])).collect())).collect()
+    }
+
+    // SRFI-48 `format`: ~a ~s ~c ~d ~b ~o ~x with an optional field width, ~w,dF, ~% ~n and ~~
+    pub fn format(control: &Value, args: &[Value]) -> Result<Value, Error> {
+        let control = control.as_string()?;
+        let mut args = args.iter();
+        let mut next_arg = |directive: char| args.next().ok_or_else(|| Error::range(format!("too few arguments for ~{}", directive)));
+        let mut out = String::new();
+        let mut chs = control.chars().peekable();
+        while let Some(c) = chs.next() {
+            if c != '~' {
+                out.push(c);
+                continue;
+            }
+
+            let mut params = vec![String::new()];
+            while let Some(&c) = chs.peek() {
+                if c.is_ascii_digit() {
+                    params.last_mut().unwrap().push(c);
+                } else if c == ',' {
+                    params.push(String::new());
+                } else {
+                    break;
+                }
+                chs.next();
+            }
+            let params = params.iter().map(|param| if param.is_empty() { Ok(None) } else { param.parse().map(Some) })
+                .collect::<Result<Vec<Option<usize>>, _>>()
+                .map_err(|err| Error::range(err.to_string()))?;
+            let width = params[0].unwrap_or(0);
+
+            let directive = chs.next().ok_or_else(|| Error::range("format string ends in '~'"))?;
+            match directive.to_ascii_lowercase() {
+                'a' => out.push_str(&format!("{:<width$}", next_arg(directive)?.display_string())),
+                's' => out.push_str(&format!("{:<width$}", next_arg(directive)?.write_string())),
+                'c' => out.push(next_arg(directive)?.as_char()?),
+                'd' => out.push_str(&format!("{:>width$}", next_arg(directive)?.number_to_string(Some(&Int(10)))?.as_string()?)),
+                'b' => out.push_str(&format!("{:>width$}", next_arg(directive)?.number_to_string(Some(&Int(2)))?.as_string()?)),
+                'o' => out.push_str(&format!("{:>width$}", next_arg(directive)?.number_to_string(Some(&Int(8)))?.as_string()?)),
+                'x' => out.push_str(&format!("{:>width$}", next_arg(directive)?.number_to_string(Some(&Int(16)))?.as_string()?)),
+                'f' => {
+                    let val = next_arg(directive)?;
+                    let formatted = match params.get(1).copied().flatten() {
+                        Some(precision) => format!("{:.precision$}", val.to_f64()?),
+                        None => val.number_to_string(None)?.as_string()?,
+                    };
+                    out.push_str(&format!("{:>width$}", formatted));
+                },
+                '%' | 'n' => out.push('\n'),
+                '~' => out.push('~'),
+                _ => return Err(Error::range(format!("unknown format directive ~{}", directive))),
+            }
+        }
+        Ok(Value::str(out))
+    }
+
+    pub fn symbol_to_string(&self) -> Result<Value, Error> {
         if let Symbol(symbol) = self {
-            Ok(Value::Str(Rc::new(symbol.clone())))
+            Ok(Value::literal_str(symbol.clone()))
         } else {
-            Err(String::from("not symbol"))
+            Err(Error::expected("symbol", self))
         }
     }
 
-    pub fn string_to_symbol(&self) -> Result<Value, String> {
+    pub fn string_to_symbol(&self) -> Result<Value, Error> {
         if let Value::Str(string) = self {
-            Ok(Symbol((**string).clone()))
+            Ok(Symbol(string.string()))
         } else {
-            Err(String::from("not string"))
+            Err(Error::expected("string", self))
         }
     }
 
-    pub fn number_to_string(&self) -> Result<Value, String> {
-        if let Value::Num(val) = self {
-            Ok(Value::Str(Rc::new(val.to_string())))
-        } else {
-            Err(String::from("not number"))
+    pub fn number_to_string(&self, radix: Option<&Value>) -> Result<Value, Error> {
+        let radix = match radix {
+            Some(radix) => radix_of(radix)?,
+            None => 10,
+        };
+        match self {
+            Int(val) => Ok(Value::str(format_int(*val, radix))),
+            Value::Num(val) if radix == 10 => Ok(Value::str(format_flonum(*val))),
+            Value::Num(val) if val.fract() == 0.0 && val.abs() < i64::MAX as f64 => Ok(Value::str(format_int(*val as i64, radix) + ".0")),
+            Value::Num(_) => Err(Error::range(format!("{:?} can not be written in radix {}", self, radix))),
+            _ => Err(Error::expected("number", self)),
         }
     }
 
-    pub fn string_to_number(&self) -> Result<Value, String> {
+    pub fn string_to_number(&self, radix: Option<&Value>) -> Result<Value, Error> {
+        let radix = match radix {
+            Some(radix) => radix_of(radix)?,
+            None => 10,
+        };
         if let Value::Str(string) = self {
-            match string.parse() {
-                Ok(val) => Ok(Value::Num(val)),
-                Err(_) => Err(String::from("can not convert to number"))
-            }
+            Ok(Self::parse_number(&string.string(), radix).unwrap_or(Value::Bool(false)))
         } else {
-            Err(String::from("not string"))
+            Err(Error::expected("string", self))
+        }
+    }
+
+    // the number written as `text` with the optional `#x`/`#e`-style prefixes, or None when it is not a number
+    pub fn parse_number(text: &str, radix: u32) -> Option<Value> {
+        let mut radix = radix;
+        let mut exactness = None;
+        let mut text = text;
+        while let Some(rest) = text.strip_prefix('#') {
+            let mut chs = rest.chars();
+            match chs.next()?.to_ascii_lowercase() {
+                'x' => radix = 16,
+                'd' => radix = 10,
+                'o' => radix = 8,
+                'b' => radix = 2,
+                'e' => exactness = Some(true),
+                'i' => exactness = Some(false),
+                _ => return None,
+            }
+            text = chs.as_str();
+        }
+
+        let value = parse_real(text, radix)?;
+        match exactness {
+            Some(true) => value.exact().ok(),
+            Some(false) => value.inexact().ok(),
+            None => Some(value),
+        }
+    }
+}
+
+fn parse_real(text: &str, radix: u32) -> Option<Value> {
+    match text {
+        "+inf.0" => return Some(Value::Num(f64::INFINITY)),
+        "-inf.0" => return Some(Value::Num(f64::NEG_INFINITY)),
+        "+nan.0" | "-nan.0" => return Some(Value::Num(f64::NAN)),
+        _ => {},
+    }
+
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if let Some((numerator, denominator)) = digits.split_once('/') {
+        let numerator = parse_uint(numerator, radix)?;
+        let denominator = parse_uint(denominator, radix)?;
+        return (Int(sign) * numerator).ok().and_then(|numerator| (numerator / denominator).ok());
+    }
+    if let Some(val) = parse_uint(digits, radix) {
+        return (Int(sign) * val).ok();
+    }
+
+    let decimal = radix == 10
+        && digits.chars().all(|c| c.is_ascii_digit() || ".eE+-".contains(c))
+        && digits.starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    match digits.parse::<f64>() {
+        Ok(val) if decimal => Some(Value::Num(sign as f64 * val)),
+        _ => None,
+    }
+}
+
+// digits in `radix` without a sign, inexact when they do not fit in an exact integer
+fn parse_uint(digits: &str, radix: u32) -> Option<Value> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    match i64::from_str_radix(digits, radix) {
+        Ok(val) => Some(Int(val)),
+        Err(_) => Some(Value::Num(digits.chars().fold(0.0, |acc, c| acc * radix as f64 + c.to_digit(radix).unwrap() as f64))),
+    }
+}
+
+fn radix_of(radix: &Value) -> Result<u32, Error> {
+    match radix {
+        Int(radix @ (2 | 8 | 10 | 16)) => Ok(*radix as u32),
+        _ => Err(Error::expected("radix (2, 8, 10 or 16)", radix)),
+    }
+}
+
+fn format_int(val: i64, radix: u32) -> String {
+    let mut digits = Vec::new();
+    let mut rest = val.unsigned_abs();
+    loop {
+        digits.push(std::char::from_digit((rest % radix as u64) as u32, radix).unwrap());
+        rest /= radix as u64;
+        if rest == 0 {
+            break;
         }
     }
+    if val < 0 {
+        digits.push('-');
+    }
+    digits.into_iter().rev().collect()
 }
 
+// the shortest representation that reads back as the same flonum, always with a decimal point or exponent
+pub fn format_flonum(val: f64) -> String {
+    if val.is_nan() {
+        String::from("+nan.0")
+    } else if val.is_infinite() {
+        String::from(if val > 0.0 { "+inf.0" } else { "-inf.0" })
+    } else {
+        format!("{:?}", val)
+    }
+}
+
+fn gcd_int(mut lhs: u64, mut rhs: u64) -> u64 {
+    while rhs != 0 {
+        (lhs, rhs) = (rhs, lhs % rhs);
+    }
+    lhs
+}
+
+fn gcd_float(lhs: f64, rhs: f64) -> f64 {
+    if rhs == 0.0 { lhs.abs() } else { gcd_float(rhs, lhs % rhs) }
+}
+
+// the characters of `string` between the optional `start` and `end` indices
+fn char_slice(string: &str, start: Option<&Value>, end: Option<&Value>) -> Result<Vec<char>, Error> {
+    let chars: Vec<char> = string.chars().collect();
+    Ok(chars[index_range(chars.len(), start, end)?].to_vec())
+}
+
+// the range between the optional `start` and `end` indices of a sequence of length `len`
+fn index_range(len: usize, start: Option<&Value>, end: Option<&Value>) -> Result<Range<usize>, Error> {
+    let start = match start {
+        Some(start) => start.to_index()?,
+        None => 0,
+    };
+    let end = match end {
+        Some(end) => end.to_index()?,
+        None => len,
+    };
+    if start <= end && end <= len {
+        Ok(start..end)
+    } else {
+        Err(Error::range(format!("range {}..{} is out of range", start, end)))
+    }
+}
+
+pub fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|(_, named)| *named == c).map(|(name, _)| *name)
+}
+
+pub const CHAR_NAMES: [(&str, char); 10] = [
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("null", '\0'),
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("escape", '\x1b'),
+    ("nul", '\0'),
+];
+
+type Frame = Vec<(String, Value)>;
+
 #[derive(Debug, Clone)]
-pub struct Env(Rc<RefCell<(Vec<(String, Value)>, Option<Env>)>>);
+pub struct Env(Rc<RefCell<(Frame, Option<Env>)>>);
 
 impl fmt::Display for Env {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -304,18 +1724,20 @@ impl fmt::Display for Env {
             parent.fmt(f)?;
         }
         let frame = &self.0.borrow().0;
-        for (ident, value) in frame {
-            let disp = if let Proc(_) = value { Symbol(String::from("procedure")) } else { value.clone() };
-            write!(f, "{}: {}, ", ident, disp)?;
+        // built-ins left as they are would bury the definitions
+        for (ident, value) in frame.iter().filter(|(ident, value)| !matches!(value, Proc(Proc::Opr(opr)) if opr == ident)) {
+            write!(f, "{}: {}, ", ident, value)?;
         }
-        write!(f, "\n")
+        writeln!(f)
     }
 }
 
 impl Env {
+    // a global environment, where definitions shadow the built-in procedures
     pub fn new() -> Self {
+        let builtins = OPERATORS.iter().map(|&opr| (opr.to_string(), Proc(Proc::Opr(opr)))).collect();
         Env(Rc::new(RefCell::new(
-            (Vec::new(), None)
+            (builtins, None)
         )))
     }
 
@@ -325,11 +1747,21 @@ impl Env {
         )))
     }
 
-    pub fn add(&self, ident: &String, value: Value) {
-        self.0.borrow_mut().0.push((ident.clone(), value));
+    pub fn add(&self, ident: &str, value: Value) {
+        self.0.borrow_mut().0.push((ident.to_string(), value));
+    }
+
+    // every bound identifier, outermost frame first
+    pub fn idents(&self) -> Vec<String> {
+        let mut idents = match &self.0.borrow().1 {
+            Some(parent) => parent.idents(),
+            None => Vec::new(),
+        };
+        idents.extend(self.0.borrow().0.iter().map(|(ident, _)| ident.clone()));
+        idents
     }
 
-    pub fn find(&self, expected: &String) -> Result<Value, String> {
+    pub fn find(&self, expected: &String) -> Result<Value, Error> {
         for (ident, value) in self.0.borrow().0.iter().rev() {
             if ident == expected {
                 return Ok(value.clone());
@@ -338,11 +1770,11 @@ impl Env {
         if let Some(parent) = &self.0.borrow().1 {
             parent.find(expected)
         } else {
-            Err(format!("{:?} is undefined", expected))
+            Err(Error::new(ErrorKind::Unbound(expected.clone())))
         }
     }
 
-    pub fn set(&mut self, expected: &String, new_value: Value) -> Result<Value, String> {
+    pub fn set(&mut self, expected: &String, new_value: Value) -> Result<Value, Error> {
         for (ident, value) in self.0.borrow_mut().0.iter_mut().rev() {
             if ident == expected {
                 *value = new_value.clone();
@@ -352,7 +1784,7 @@ impl Env {
         if let Some(parent) = &mut self.0.borrow_mut().1 {
             parent.set(expected, new_value)
         } else {
-            Err(format!("{:?} is undefined", expected))
+            Err(Error::new(ErrorKind::Unbound(expected.clone())))
         }
     }
 }
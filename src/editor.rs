@@ -0,0 +1,60 @@
+use crate::data::Env;
+use crate::lexer::{KEYWORDS, is_ident_char};
+
+use std::borrow::Cow;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+// completes keywords and the identifiers bound in `env`, and highlights matching parens
+pub struct ReplHelper {
+    pub env: Env,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl ReplHelper {
+    pub fn new(env: Env) -> Self {
+        ReplHelper { env, brackets: MatchingBracketHighlighter::new() }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].trim_end_matches(is_ident_char).len();
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let mut candidates: Vec<String> = KEYWORDS.iter().map(|name| name.to_string())
+            .chain(self.env.idents())
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
@@ -0,0 +1,165 @@
+use crate::data::{Span, Value};
+
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    Lex(String),
+    Parse(String),
+    Unbound(String),
+    Type { expected: &'static str, value: Box<Value> },
+    Arity { expected: Option<usize>, actual: usize },
+    Range(String),
+    User { message: String, irritants: Vec<Value> },
+    // a non-error object passed to `raise`
+    Raise(Box<Value>),
+    IO(String),
+    // `exit` unwinding to main with the process status
+    Exit(i32),
+}
+
+// a lambda being applied, and the span it was called from unless a built-in called it
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub proc: Option<String>,
+    pub site: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub proc: Option<String>,
+    pub span: Option<Span>,
+    // the call stack when the error left the innermost lambda, outermost first
+    pub trace: Vec<CallFrame>,
+    // whether the exception handlers were called with it already
+    pub signalled: bool,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Error { kind, proc: None, span: None, trace: Vec::new(), signalled: false }
+    }
+
+    pub fn expected(expected: &'static str, value: &Value) -> Self {
+        Error::new(ErrorKind::Type { expected, value: Box::new(value.clone()) })
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Parse(message.into()))
+    }
+
+    pub fn range(message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::Range(message.into()))
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        Error::new(ErrorKind::IO(message.into()))
+    }
+
+    // locate the error at `span` unless a more precise location is already known
+    pub fn at(mut self, span: &Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+        self
+    }
+
+    // attribute the error to the procedure `proc` unless it was raised inside another one or by the program itself
+    pub fn in_proc(mut self, proc: &str) -> Self {
+        if self.proc.is_none() && !matches!(self.kind, ErrorKind::User { .. } | ErrorKind::Raise(_) | ErrorKind::Exit(_)) {
+            self.proc = Some(proc.to_string());
+        }
+        self
+    }
+
+    pub fn with_trace(mut self, trace: &[CallFrame]) -> Self {
+        if self.trace.is_empty() {
+            self.trace = trace.to_vec();
+        }
+        self
+    }
+
+    // each procedure the error passed through, innermost first, with where it was executing
+    pub fn backtrace(&self) -> Vec<(String, Option<Span>)> {
+        let mut lines = Vec::new();
+        let mut location = self.span.clone();
+        for frame in self.trace.iter().rev() {
+            let name = frame.proc.clone().unwrap_or_else(|| String::from("anonymous procedure"));
+            lines.push((name, location));
+            location = frame.site.clone();
+        }
+        if !self.trace.is_empty() {
+            lines.push((String::from("top level"), location));
+        }
+        lines
+    }
+
+    // the object a Scheme handler receives for the error
+    pub fn into_value(self) -> Value {
+        match self.kind {
+            ErrorKind::Raise(value) => *value,
+            _ => Value::ErrorObject(Rc::new(self)),
+        }
+    }
+
+    // the message without location
+    pub fn message(&self) -> String {
+        match &self.proc {
+            Some(proc) => format!("{}: {}", proc, self.kind),
+            None => self.kind.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Lex(message) | ErrorKind::Parse(message) | ErrorKind::Range(message) | ErrorKind::IO(message) => write!(f, "{}", message),
+            ErrorKind::Unbound(ident) => write!(f, "{} is unbound", ident),
+            ErrorKind::Type { expected, value } => write!(f, "expected {}, got {:?}", expected, value),
+            ErrorKind::Arity { expected: Some(expected), actual } => write!(f, "expected {} arguments, got {}", expected, actual),
+            ErrorKind::Arity { expected: None, actual } => write!(f, "can not take {} arguments", actual),
+            ErrorKind::User { message, irritants } => {
+                write!(f, "{}", message)?;
+                for irritant in irritants {
+                    write!(f, " {:?}", irritant)?;
+                }
+                Ok(())
+            },
+            ErrorKind::Raise(value) => write!(f, "uncaught {:?}", value),
+            ErrorKind::Exit(status) => write!(f, "exit with status {}", status),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "{}: {}\n{}", span, self.message(), span.excerpt())?,
+            None => write!(f, "{}", self.message())?,
+        }
+
+        // runs of the same frame, as left by recursion, are collapsed into one line
+        let mut lines = self.backtrace().into_iter().map(|(name, location)| match location {
+            Some(location) => format!("{} ({})", name, location),
+            None => name,
+        }).peekable();
+        let mut first = true;
+        while let Some(line) = lines.next() {
+            let mut times = 1;
+            while lines.next_if_eq(&line).is_some() {
+                times += 1;
+            }
+            write!(f, "\n  {} {}", if first { "in" } else { "called from" }, line)?;
+            if times > 1 {
+                write!(f, " [{} times]", times)?;
+            }
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
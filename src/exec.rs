@@ -1,37 +1,158 @@
-use crate::data::{TopLevel, Body, Defn, Expr, Value, Proc, Env};
+use crate::data::{TopLevel, Body, Defn, Expr, Value, Proc, Cons, Env, Printer, Span};
+use crate::error::{Error, ErrorKind, CallFrame};
+use crate::port::{self, Port};
+use crate::pretty;
 
+use std::fs;
+use std::path::Path;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::process;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use Expr::*;
 use Value::*;
 
-pub fn exec(nodes: Vec<TopLevel>) -> Result<(), String> {
-    let mut env = Env::new();
+// the built-in procedures, bound in the outermost frame of every global environment
+pub const OPERATORS: [&str; 218] = [
+    "eq?", "neq?", "equal?",
+    "list?", "pair?", "procedure?", "symbol?", "boolean?", "char?", "string?", "null?", "procedure?",
+    "list", "length", "memq", "last", "append",
+    "values", "call-with-values", "dynamic-wind",
+    "error", "raise", "raise-continuable", "with-exception-handler",
+    "error-object?", "error-object-message", "error-object-irritants", "error-object-trace", "file-error?", "read-error?",
+    "fold", "fold-right", "reduce", "filter", "remove", "partition", "delete", "delete-duplicates",
+    "iota", "take", "drop", "append-map", "filter-map", "find", "find-tail", "any", "every", "count",
+    "last-pair", "list-index",
+    "cons", "car", "cdr", "set-car!", "set-cdr!",
+    "not",
+    "=", "<=", "<", ">=", ">", "+", "-", "*", "/",
+    "number?", "integer?", "exact?", "inexact?", "exact", "inexact",
+    "zero?", "positive?", "negative?", "odd?", "even?", "min", "max", "abs",
+    "quotient", "remainder", "modulo", "floor/", "floor-quotient", "floor-remainder",
+    "truncate/", "truncate-quotient", "truncate-remainder", "gcd", "lcm", "numerator", "denominator",
+    "floor", "ceiling", "round", "truncate", "square", "sqrt", "exact-integer-sqrt", "expt",
+    "exp", "log", "sin", "cos", "tan", "asin", "acos", "atan",
+    "string-length", "string-ref", "substring", "string-copy", "string-append",
+    "make-string", "string-set!", "string-fill!", "string-copy!",
+    "string=?", "string<?", "string>?", "string<=?", "string>=?",
+    "string-ci=?", "string-ci<?", "string-ci>?", "string-ci<=?", "string-ci>=?",
+    "string->list", "list->string", "string-upcase", "string-downcase",
+    "string-contains", "string-index", "string-join", "string-split",
+    "string-trim", "string-trim-right", "string-trim-both", "string-pad", "string-pad-right",
+    "string-for-each", "string-map",
+    "symbol->string", "string->symbol", "number->string", "string->number",
+    "current-input-port", "current-output-port", "current-error-port",
+    "port?", "input-port?", "output-port?",
+    "display", "write", "write-shared", "write-simple", "pretty-print", "pretty-print-width", "format", "write-string", "write-char", "newline",
+    "read-char", "peek-char", "read-line", "read-string", "char-ready?",
+    "eof-object", "eof-object?", "close-port", "close-input-port", "close-output-port", "flush-output-port",
+    "open-input-string", "open-output-string", "get-output-string", "with-output-to-string", "call-with-output-string",
+    "open-input-file", "open-output-file", "call-with-input-file", "call-with-output-file",
+    "with-input-from-file", "with-output-to-file", "file-exists?", "delete-file",
+    "bytevector?", "make-bytevector", "bytevector", "bytevector-length", "bytevector-u8-ref", "bytevector-u8-set!",
+    "bytevector-copy", "bytevector-copy!", "bytevector-append", "utf8->string", "string->utf8",
+    "binary-port?", "textual-port?", "open-input-bytevector", "open-output-bytevector", "get-output-bytevector",
+    "read-u8", "peek-u8", "u8-ready?", "read-bytevector", "write-u8", "write-bytevector",
+    "read",
+    "print",
+    "command-line", "exit", "emergency-exit", "get-environment-variable", "get-environment-variables",
+    "current-second", "current-jiffy", "jiffies-per-second",
+];
+
+thread_local! {
+    static CALL_STACK: RefCell<Vec<CallFrame>> = const { RefCell::new(Vec::new()) };
+    // handlers installed by `with-exception-handler`, innermost last, with None where a `guard` catches errors
+    static HANDLERS: RefCell<Vec<Option<Value>>> = const { RefCell::new(Vec::new()) };
+    // expressions evaluated so far, for timing in the repl
+    static STEPS: Cell<u64> = const { Cell::new(0) };
+    // the program name followed by its arguments, as returned by `command-line`
+    static COMMAND_LINE: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    // where `current-jiffy` counts from
+    static EPOCH: Instant = Instant::now();
+}
+
+pub fn set_command_line(args: Vec<String>) {
+    COMMAND_LINE.with(|command_line| *command_line.borrow_mut() = args);
+}
+
+pub fn steps() -> u64 {
+    STEPS.with(Cell::get)
+}
+
+pub fn exec(nodes: Vec<TopLevel>, env: &mut Env) -> Result<(), Error> {
     for node in nodes {
-        exec_line(node, &mut env)?;
+        if let Some(value) = exec_line(node, env)? {
+            port::current_output().write_str(&format!("{}\n", pretty::pretty_print(&value)))?;
+        }
     }
     Ok(())
 }
 
-pub fn exec_line(node: TopLevel, env: &mut Env) -> Result<(), String> {
+// the value of an expression, or nothing for a definition or an unspecified value
+pub fn exec_line(node: TopLevel, env: &mut Env) -> Result<Option<Value>, Error> {
     match node {
-        TopLevel::Defn(defn) => bind(defn, env)?,
-        TopLevel::Expr(expr) => println!("{}", eval(expr, env)?),
+        TopLevel::Defn(defn) => {
+            bind(defn, env)?;
+            Ok(None)
+        },
+        TopLevel::Expr(expr) => eval(expr, env).map(|value| Some(value).filter(|value| !matches!(value, Unspecified))),
     }
-    Ok(())
 }
 
-fn bind(defn: Defn, env: &mut Env) -> Result<(), String> {
+fn bind(defn: Defn, env: &mut Env) -> Result<(), Error> {
     let Defn { ident, expr } = defn;
-    let value = eval(expr, env)?;
+    let value = eval(expr, env)?.named(&ident);
     env.add(&ident, value);
     Ok(())
 }
 
-fn eval(expr: Expr, env: &mut Env) -> Result<Value, String> {
+fn eval(expr: Expr, env: &mut Env) -> Result<Value, Error> {
+    STEPS.with(|steps| steps.set(steps.get() + 1));
+    // an error not located yet points at the innermost expression it left
+    let span = expr.span().clone();
+    match eval_expr(expr, env) {
+        // the handlers run where the error is raised, before it unwinds
+        Err(err) if !err.signalled && !matches!(err.kind, ErrorKind::Exit(_)) => Err(signal(err.at(&span))),
+        result => result.map_err(|err| err.at(&span)),
+    }
+}
+
+// calls the current handler with `err` and gives the error to unwind with: `err` itself when there is no handler
+// or a `guard` catches it, or else what the handler raised, returning from it being an error of its own
+fn signal(err: Error) -> Error {
+    let err = CALL_STACK.with(|stack| Error { signalled: true, ..err.with_trace(&stack.borrow()) });
+    let raised = with_current_handler(|handler| match apply_value(handler, vec![err.clone().into_value()]) {
+        Ok(_) => {
+            let message = String::from("exception handler returned");
+            signal(Error { span: err.span.clone(), trace: err.trace.clone(), ..Error::new(ErrorKind::User { message, irritants: vec![err.clone().into_value()] }) })
+        },
+        Err(err) => err,
+    });
+    raised.unwrap_or(err)
+}
+
+// runs `f` with `handler` installed, or with a `guard` catching errors when None
+fn with_handler<T>(handler: Option<Value>, f: impl FnOnce() -> T) -> T {
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(handler));
+    let result = f();
+    HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    result
+}
+
+// runs `f` on the current handler with the one outside it current meanwhile, or gives None without a handler to call
+fn with_current_handler<T>(f: impl FnOnce(&Value) -> T) -> Option<T> {
+    let handler = HANDLERS.with(|handlers| handlers.borrow().last().cloned().flatten())?;
+    HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    let result = f(&handler);
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(Some(handler)));
+    Some(result)
+}
+
+fn eval_expr(expr: Expr, env: &mut Env) -> Result<Value, Error> {
     match expr {
-        Let { binds, body } => {
+        Let { binds, body, .. } => {
             let binds = binds.into_iter().map(|(ident, expr)| (ident, eval(expr, env))).collect::<Vec<_>>();
             let env = &mut env.push_frame();
             for (ident, value) in binds {
@@ -39,7 +160,7 @@ fn eval(expr: Expr, env: &mut Env) -> Result<Value, String> {
             }
             eval_body(body, env)
         },
-        LetStar { binds, body } => {
+        LetStar { binds, body, .. } => {
             let env = &mut env.push_frame();
             for (ident, expr) in binds {
                 let value = eval(expr, env)?;
@@ -47,79 +168,85 @@ fn eval(expr: Expr, env: &mut Env) -> Result<Value, String> {
             }
             eval_body(body, env)
         },
-        LetRec { binds, body } => {
+        LetRec { binds, body, .. } => {
             let env = &mut env.push_frame();
             for (ident, _) in &binds {
                 env.add(ident, Value::Nil);
             }
             for (ident, expr) in binds {
-                let value = eval(expr, env)?;
+                let value = eval(expr, env)?.named(&ident);
                 env.set(&ident, value)?;
             }
             eval_body(body, env)
         },
-        Set { ident, expr } => {
+        Set { ident, expr, .. } => {
             let value = eval(*expr, env)?;
-            env.set(&ident, value)
+            env.set(&ident, value)?;
+            Ok(Unspecified)
         },
-        Var(ident) => env.find(&ident),
-        Quote(s_expr) => Ok(*s_expr),
-        Begin(exprs) => {
+        Var(ident, _) => env.find(&ident),
+        Quote(s_expr, _) => Ok(*s_expr),
+        Begin(exprs, _) => {
             let mut value = Value::Nil;
             for expr in exprs {
                 value = eval(expr, env)?;
             }
             Ok(value)
         },
-        If { cond, expr1, expr2 } => {
-            if let Value::Bool(cond) = eval(*cond.clone(), env)? {
-                if cond { eval(*expr1, env) } else { eval(*expr2, env) }
-            } else {
-                Err(format!("{:?} is not condition", *cond))
+        If { cond, expr1, expr2, .. } => {
+            match eval(*cond, env)? {
+                Value::Bool(cond) => if cond { eval(*expr1, env) } else { eval(*expr2, env) },
+                cond => Err(Error::expected("boolean", &cond)),
             }
         },
-        Cond { cond_then } => {
-            for (cond, then) in cond_then {
-                match cond {
-                    Var(ident) if &ident == "else" => return eval(then, env),
-                    _ => {},
-                }
-                if let Value::Bool(cond) = eval(cond.clone(), env)? {
-                    if cond { return eval(then, env); }
-                } else {
-                    return Err(format!("{:?} is not condition", cond))
-                }
+        Cond { cond_then, .. } => Ok(eval_clauses(cond_then, env)?.unwrap_or(Value::Nil)),
+        Guard { var, cond_then, body, .. } => {
+            match with_handler(None, || eval_body(body, &mut env.push_frame())) {
+                Err(err) if !matches!(err.kind, ErrorKind::Exit(_)) => {
+                    let env = &mut env.push_frame();
+                    env.add(&var, err.clone().into_value());
+                    match eval_clauses(cond_then, env)? {
+                        Some(value) => Ok(value),
+                        // raised again to the handlers outside when no clause applies
+                        None => Err(Error { signalled: false, ..err }),
+                    }
+                },
+                result => result,
             }
-            Ok(Value::Nil)
         },
-        And { args } => {
+        And { args, .. } => {
             for arg in args {
                 match eval(arg, env)? {
                     Value::Bool(true) => {},
                     Value::Bool(false) => return Ok(Value::Bool(false)),
-                    _ => return Err(String::from("not boolean")),
+                    value => return Err(Error::expected("boolean", &value)),
                 }
             }
             Ok(Value::Bool(true))
         },
-        Or { args } => {
+        Or { args, .. } => {
             for arg in args {
                 match eval(arg, env)? {
                     Value::Bool(true) => return Ok(Value::Bool(true)),
                     Value::Bool(false) => {},
-                    _ => return Err(String::from("not boolean")),
+                    value => return Err(Error::expected("boolean", &value)),
                 }
             }
             Ok(Value::Bool(false))
         },
-        Do { binds, test, exprs, body } => {
+        Do { binds, test, exprs, body, .. } => {
             let binds = binds.into_iter().map(|(ident, init, update)| (ident, eval(init, env), update)).collect::<Vec<_>>();
             let env = &mut env.push_frame();
             for (ident, init, _) in &binds {
                 env.add(ident, init.clone()?);
             }
 
-            while eval(*test.clone(), env)? != Value::Bool(true) {
+            loop {
+                match eval(*test.clone(), env)? {
+                    Value::Bool(true) => break,
+                    Value::Bool(false) => {},
+                    test => return Err(Error::expected("boolean", &test)),
+                }
                 eval(*body.clone(), env)?;
                 for (ident, _, update) in &binds {
                     let value = eval(update.clone(), env)?;
@@ -134,33 +261,40 @@ fn eval(expr: Expr, env: &mut Env) -> Result<Value, String> {
 
             Ok(value)
         },
-        Apply { proc, args } => {
-            let Proc(proc) = eval(*proc.clone(), env)? else {
-                return Err(format!("{:?} is not procedure", proc));
+        Apply { proc, args, span } => {
+            let proc = match eval(*proc, env)? {
+                Proc(proc) => proc,
+                value => return Err(Error::expected("procedure", &value)),
             };
             let args = args.into_iter().map(|arg| eval(arg, env)).collect::<Result<_, _>>()?;
-
-            match proc {
-                Proc::Opr(opr) => eval_opr(opr, args),
-                Proc::Lambda { env, params, body } => {
-                    let env = &mut env.push_frame();
-                    for (param, arg) in params.into_iter().zip(args.into_iter()) {
-                        env.add(&param, arg);
-                    }
-                    eval_body(body, env)
-                },
-            }
+            call(proc, args, Some(&span))
         },
-        Lambda { params, body } => Ok(Proc(Proc::Lambda { env: env.push_frame(), params, body })),
-        Expr::Opr(opr) => Ok(Proc(Proc::Opr(opr))),
-        Expr::Num(val) => Ok(Value::Num(val)),
-        Expr::Bool(val) => Ok(Value::Bool(val)),
-        Expr::Str(val) => Ok(Value::Str(Rc::new(val))),
-        Expr::Nil => Ok(Value::Nil),
+        Lambda { params, body, .. } => Ok(Proc(Proc::Lambda { name: None, env: env.push_frame(), params, body })),
+        Expr::Int(val, _) => Ok(Value::Int(val)),
+        Expr::Num(val, _) => Ok(Value::Num(val)),
+        Expr::Bool(val, _) => Ok(Value::Bool(val)),
+        Expr::Char(val, _) => Ok(Value::Char(val)),
+        Expr::Str(val, _) => Ok(Value::literal_str(val)),
+        Expr::Nil(_) => Ok(Value::Nil),
+    }
+}
+
+// the value of the first clause whose test holds, or None when none does
+fn eval_clauses(cond_then: Vec<(Expr, Expr)>, env: &mut Env) -> Result<Option<Value>, Error> {
+    for (cond, then) in cond_then {
+        match cond {
+            Var(ident, _) if &ident == "else" => return eval(then, env).map(Some),
+            _ => {},
+        }
+        match eval(cond, env)? {
+            Value::Bool(cond) => if cond { return eval(then, env).map(Some); },
+            cond => return Err(Error::expected("boolean", &cond)),
+        }
     }
+    Ok(None)
 }
 
-fn eval_body(body: Body, env: &mut Env) -> Result<Value, String> {
+fn eval_body(body: Body, env: &mut Env) -> Result<Value, Error> {
     let mut value = Value::Nil;
     for defn in body.defns {
         bind(defn, env)?;
@@ -171,100 +305,532 @@ fn eval_body(body: Body, env: &mut Env) -> Result<Value, String> {
     Ok(value)
 }
 
-fn eval_opr(operator: &'static str, args: Vec<Value>) -> Result<Value, String> {
+// `apply` keeping track of lambdas on the call stack
+fn call(proc: Proc, args: Vec<Value>, site: Option<&Span>) -> Result<Value, Error> {
+    let Proc::Lambda { name, .. } = &proc else {
+        return apply(proc, args);
+    };
+    CALL_STACK.with(|stack| stack.borrow_mut().push(CallFrame { proc: name.clone(), site: site.cloned() }));
+    let result = apply(proc, args).map_err(|err| CALL_STACK.with(|stack| err.with_trace(&stack.borrow())));
+    CALL_STACK.with(|stack| stack.borrow_mut().pop());
+    result
+}
+
+fn apply(proc: Proc, args: Vec<Value>) -> Result<Value, Error> {
+    match proc {
+        Proc::Opr(opr) => eval_opr(opr, args).map_err(|err| err.in_proc(opr)),
+        Proc::Lambda { name, env, params, body } => {
+            if params.len() != args.len() {
+                let err = Error::new(ErrorKind::Arity { expected: Some(params.len()), actual: args.len() });
+                return Err(match name {
+                    Some(name) => err.in_proc(&name),
+                    None => err,
+                });
+            }
+            let env = &mut env.push_frame();
+            for (param, arg) in params.into_iter().zip(args) {
+                env.add(&param, arg);
+            }
+            eval_body(body, env)
+        },
+    }
+}
+
+fn apply_value(proc: &Value, args: Vec<Value>) -> Result<Value, Error> {
+    if let Proc(proc) = proc {
+        call(proc.clone(), args, None)
+    } else {
+        Err(Error::expected("procedure", proc))
+    }
+}
+
+fn eval_opr(operator: &'static str, args: Vec<Value>) -> Result<Value, Error> {
     match (operator, args.len()) {
-        ("cons", 2) => Ok(Pair(Rc::new(RefCell::new((args[0].clone(), args[1].clone()))))),
+        ("cons", 2) => Ok(Pair(Rc::new(RefCell::new(Cons(args[0].clone(), args[1].clone()))))),
         ("car" , 1) => {
             if let Pair(pair) = &args[0] {
                 Ok((pair.borrow().0).clone())
             } else {
-                Err(format!("{:?} is not pair", args[0]))
+                Err(Error::expected("pair", &args[0]))
             }
         },
         ("cdr" , 1) => {
             if let Pair(pair) = &args[0] {
                 Ok((pair.borrow().1).clone())
             } else {
-                Err(format!("{:?} is not pair", args[0]))
+                Err(Error::expected("pair", &args[0]))
             }
         },
         ("set-car!", 2) => {
             if let Pair(pair) = &args[0] {
                 pair.borrow_mut().0 = args[1].clone();
-                Ok(Pair(Rc::clone(pair)))
+                Ok(Unspecified)
             } else {
-                Err(format!("{:?} is not pair", args[0]))
+                Err(Error::expected("pair", &args[0]))
             }
         },
         ("set-cdr!", 2) => {
             if let Pair(pair) = &args[0] {
                 pair.borrow_mut().1 = args[1].clone();
-                Ok(Pair(Rc::clone(pair)))
+                Ok(Unspecified)
             } else {
-                Err(format!("{:?} is not pair", args[0]))
+                Err(Error::expected("pair", &args[0]))
             }
         },
         ("not", 1) => {
             if let Value::Bool(val) = args[0] {
                 Ok(Value::Bool(!val))
             } else {
-                Err(format!("'{:?}' is not boolean", args[0]))
+                Err(Error::expected("boolean", &args[0]))
             }
         },
         ("eq?"   , 2) => Ok(Value::Bool(Value::eq(&args[0], &args[1]))),
         ("neq?"  , 2) => Ok(Value::Bool(!Value::eq(&args[0], &args[1]))),
         ("equal?", 2) => Ok(Value::Bool(Value::equal(&args[0], &args[1]))),
         ("list?", 1) => Ok(Value::Bool(args[0].is_list())),
-        (ident, 1) if ["pair?", "procedure?", "symbol?", "number?", "boolean?", "string?", "null?"].contains(&ident) => {
+        (ident, 1) if ["pair?", "procedure?", "symbol?", "number?", "integer?", "boolean?", "char?", "string?", "bytevector?", "null?"].contains(&ident) => {
             match (operator, &args[0]) {
                 ("pair?"     , Pair(_)       ) |
                 ("procedure?", Proc(_)       ) |
                 ("symbol?"   , Symbol(_)     ) |
-                ("number?"   , Value::Num(_) ) |
+                ("number?"   , Value::Int(_) | Value::Num(_)) |
                 ("boolean?"  , Value::Bool(_)) |
+                ("char?"     , Value::Char(_)) |
                 ("string?"   , Value::Str(_) ) |
+                ("bytevector?", Value::Bytevector(_)) |
                 ("null?"     , Value::Nil    ) => Ok(Value::Bool(true)),
+                ("integer?"  , value) => Ok(Value::Bool(value.is_integer())),
                 _ => Ok(Value::Bool(false)),
             }
         },
         ("list"  , _) => Ok(Value::list(args)),
-        ("length", 1) => args[0].length().map(|val| Value::Num(val as f32)),
+        ("length", 1) => args[0].length().map(|val| Value::Int(val as i64)),
         ("last"  , 1) => args[0].last(),
         ("memq"  , 2) => Ok(Value::memq(&args[0], &args[1])),
         ("append", 2) => Value::append(&args[0], &args[1]),
-        ("=" , _) => Ok(Value::Bool(args.windows(2).all(|p| p[0] == p[1]))),
+        ("=" , _) => Ok(Value::Bool(args.windows(2).all(|p| p[0].partial_cmp(&p[1]).is_some_and(|ord| ord.is_eq())))),
         ("<" , _) => Ok(Value::Bool(args.windows(2).all(|p| p[0] <  p[1]))),
         ("<=", _) => Ok(Value::Bool(args.windows(2).all(|p| p[0] <= p[1]))),
         (">" , _) => Ok(Value::Bool(args.windows(2).all(|p| p[0] >  p[1]))),
         (">=", _) => Ok(Value::Bool(args.windows(2).all(|p| p[0] >= p[1]))),
-        ("+" , _) => args.into_iter().fold(Ok(Value::Num(0.0)), |sum, val| sum.and_then(|sum| sum + val)),
+        ("+" , _) => args.into_iter().try_fold(Value::Int(0), |sum, val| sum + val),
+        ("-" , 1) => args[0].negate(),
         ("-" , _) => {
             let (minuend, subtrahends) = {
                 let mut args = args.into_iter();
-                (args.next().ok_or(String::from("'-' requires at least 1 argument")), args)
+                (args.next().ok_or(Error::new(ErrorKind::Arity { expected: None, actual: 0 })), args)
             };
             subtrahends.fold(minuend, |sum, val| sum.and_then(|sum| sum - val))
         },
-        ("*" , _) => args.into_iter().fold(Ok(Value::Num(1.0)), |prod, val| prod.and_then(|prod| prod * val)),
+        ("*" , _) => args.into_iter().try_fold(Value::Int(1), |prod, val| prod * val),
+        ("/" , 1) => Value::Int(1) / args[0].clone(),
         ("/" , _) => {
             let (dividend, divisors) = {
                 let mut args = args.into_iter();
-                (args.next().ok_or(String::from("'-' requires at least 1 argument")), args)
+                (args.next().ok_or(Error::new(ErrorKind::Arity { expected: None, actual: 0 })), args)
             };
             divisors.fold(dividend, |prod, val| prod.and_then(|prod| prod / val))
         },
+        ("exact?"  , 1) => args[0].is_exact().map(Value::Bool),
+        ("inexact?", 1) => args[0].is_exact().map(|exact| Value::Bool(!exact)),
+        ("exact"   , 1) => args[0].exact(),
+        ("inexact" , 1) => args[0].inexact(),
+        ("zero?"    , 1) => args[0].sign().map(|ord| Value::Bool(ord.is_eq())),
+        ("positive?", 1) => args[0].sign().map(|ord| Value::Bool(ord.is_gt())),
+        ("negative?", 1) => args[0].sign().map(|ord| Value::Bool(ord.is_lt())),
+        ("odd?" , 1) => args[0].is_odd().map(Value::Bool),
+        ("even?", 1) => args[0].is_odd().map(|odd| Value::Bool(!odd)),
+        ("min", _) => Value::min_max(&args, false),
+        ("max", _) => Value::min_max(&args, true),
+        ("abs", 1) => args[0].abs(),
+        ("quotient" | "truncate-quotient", 2) => Ok(Value::integer_div(&args[0], &args[1], false)?.0),
+        ("remainder" | "truncate-remainder", 2) => Ok(Value::integer_div(&args[0], &args[1], false)?.1),
+        ("modulo" | "floor-remainder", 2) => Ok(Value::integer_div(&args[0], &args[1], true)?.1),
+        ("floor-quotient", 2) => Ok(Value::integer_div(&args[0], &args[1], true)?.0),
+        ("floor/", 2) => {
+            let (quo, rem) = Value::integer_div(&args[0], &args[1], true)?;
+            Ok(Values(vec![quo, rem]))
+        },
+        ("truncate/", 2) => {
+            let (quo, rem) = Value::integer_div(&args[0], &args[1], false)?;
+            Ok(Values(vec![quo, rem]))
+        },
+        ("gcd", _) => Value::gcd(&args),
+        ("lcm", _) => Value::lcm(&args),
+        ("numerator"  , 1) => args[0].numerator(),
+        ("denominator", 1) => args[0].denominator(),
+        ("floor"   , 1) => args[0].floor(),
+        ("ceiling" , 1) => args[0].ceiling(),
+        ("round"   , 1) => args[0].round(),
+        ("truncate", 1) => args[0].truncate(),
+        ("square", 1) => args[0].clone() * args[0].clone(),
+        ("sqrt", 1) => args[0].sqrt(),
+        ("exact-integer-sqrt", 1) => args[0].exact_integer_sqrt(),
+        ("expt", 2) => Value::expt(&args[0], &args[1]),
+        ("exp" , 1) => args[0].transcendental(f64::exp),
+        ("log" , 1..=2) => args[0].log(args.get(1)),
+        ("sin" , 1) => args[0].transcendental(f64::sin),
+        ("cos" , 1) => args[0].transcendental(f64::cos),
+        ("tan" , 1) => args[0].transcendental(f64::tan),
+        ("asin", 1) => args[0].transcendental(f64::asin),
+        ("acos", 1) => args[0].transcendental(f64::acos),
+        ("atan", 1..=2) => args[0].atan(args.get(1)),
+        ("values", 1) => Ok(args[0].clone()),
+        ("values", _) => Ok(Values(args)),
+        ("call-with-values", 2) => {
+            let values = match apply_value(&args[0], vec![])? {
+                Values(values) => values,
+                value => vec![value],
+            };
+            apply_value(&args[1], values)
+        },
+        ("error", n) if n >= 1 => {
+            let message = match &args[0] {
+                Value::Str(string) => string.string(),
+                value => value.write_string(),
+            };
+            Err(Error::new(ErrorKind::User { message, irritants: args[1..].to_vec() }))
+        },
+        ("raise", 1) => match &args[0] {
+            ErrorObject(err) => Err(Error { signalled: false, ..(**err).clone() }),
+            value => Err(Error::new(ErrorKind::Raise(Box::new(value.clone())))),
+        },
+        ("raise-continuable", 1) => with_current_handler(|handler| apply_value(handler, vec![args[0].clone()]))
+            .unwrap_or_else(|| Err(Error::new(ErrorKind::Raise(Box::new(args[0].clone()))))),
+        ("with-exception-handler", 2) => with_handler(Some(args[0].clone()), || apply_value(&args[1], vec![])),
+        // `after` also runs when `thunk` is left by an error or `exit`
+        ("dynamic-wind", 3) => {
+            apply_value(&args[0], vec![])?;
+            let result = apply_value(&args[1], vec![]);
+            apply_value(&args[2], vec![])?;
+            result
+        },
+        ("error-object?", 1) => Ok(Value::Bool(matches!(args[0], ErrorObject(_)))),
+        ("error-object-message", 1) => match &args[0] {
+            ErrorObject(err) => match &err.kind {
+                ErrorKind::User { message, .. } => Ok(Value::str(message.clone())),
+                _ => Ok(Value::str(err.message())),
+            },
+            value => Err(Error::expected("error object", value)),
+        },
+        ("error-object-irritants", 1) => match &args[0] {
+            ErrorObject(err) => match &err.kind {
+                ErrorKind::User { irritants, .. } => Ok(Value::list(irritants.clone())),
+                ErrorKind::Type { value, .. } => Ok(Value::list(vec![(**value).clone()])),
+                _ => Ok(Value::Nil),
+            },
+            value => Err(Error::expected("error object", value)),
+        },
+        ("error-object-trace", 1) => match &args[0] {
+            ErrorObject(err) => Ok(Value::list(err.backtrace().into_iter().map(|(name, location)| {
+                Value::list(vec![Value::str(name), location.map_or(Value::Bool(false), |location| Value::str(location.to_string()))])
+            }).collect())),
+            value => Err(Error::expected("error object", value)),
+        },
+        ("file-error?", 1) => Ok(Value::Bool(matches!(&args[0], ErrorObject(err) if matches!(err.kind, ErrorKind::IO(_))))),
+        ("read-error?", 1) => Ok(Value::Bool(matches!(&args[0], ErrorObject(err) if matches!(err.kind, ErrorKind::Lex(_) | ErrorKind::Parse(_))))),
+        ("fold", n) if n >= 3 => Value::fold(&args[2..], args[1].clone(), |row| apply_value(&args[0], row)),
+        ("fold-right", n) if n >= 3 => Value::fold_right(&args[2..], args[1].clone(), |row| apply_value(&args[0], row)),
+        ("reduce", 3) => Value::reduce(&args[2], args[1].clone(), |elem, acc| apply_value(&args[0], vec![elem, acc])),
+        ("filter", 2) => Ok(Value::partition(&args[1], |elem| Ok(apply_value(&args[0], vec![elem])?.is_true()))?.0),
+        ("remove", 2) => Ok(Value::partition(&args[1], |elem| Ok(apply_value(&args[0], vec![elem])?.is_true()))?.1),
+        ("partition", 2) => {
+            let (ins, outs) = Value::partition(&args[1], |elem| Ok(apply_value(&args[0], vec![elem])?.is_true()))?;
+            Ok(Values(vec![ins, outs]))
+        },
+        ("delete", 2) => Ok(Value::partition(&args[1], |elem| Ok(!Value::equal(&args[0], &elem)))?.0),
+        ("delete", 3) => Ok(Value::partition(&args[1], |elem| Ok(!apply_value(&args[2], vec![args[0].clone(), elem])?.is_true()))?.0),
+        ("delete-duplicates", 1) => Value::delete_duplicates(&args[0], |lhs, rhs| Ok(Value::equal(&lhs, &rhs))),
+        ("delete-duplicates", 2) => Value::delete_duplicates(&args[0], |lhs, rhs| Ok(apply_value(&args[1], vec![lhs, rhs])?.is_true())),
+        ("iota", 1) => Value::iota(&args[0], &Value::Int(0), &Value::Int(1)),
+        ("iota", 2) => Value::iota(&args[0], &args[1], &Value::Int(1)),
+        ("iota", 3) => Value::iota(&args[0], &args[1], &args[2]),
+        ("take", 2) => Value::take(&args[0], &args[1]),
+        ("drop", 2) => Value::drop(&args[0], &args[1]),
+        ("append-map", n) if n >= 2 => Value::append_map(&args[1..], |row| apply_value(&args[0], row)),
+        ("filter-map", n) if n >= 2 => Value::filter_map(&args[1..], |row| apply_value(&args[0], row)),
+        ("find", 2) => {
+            match Value::find_tail(&args[1], |elem| Ok(apply_value(&args[0], vec![elem])?.is_true()))? {
+                Pair(pair) => {
+                    let elem = pair.borrow().0.clone();
+                    Ok(elem)
+                },
+                value => Ok(value),
+            }
+        },
+        ("find-tail", 2) => Value::find_tail(&args[1], |elem| Ok(apply_value(&args[0], vec![elem])?.is_true())),
+        ("any", n) if n >= 2 => Value::any(&args[1..], |row| apply_value(&args[0], row)),
+        ("every", n) if n >= 2 => Value::every(&args[1..], |row| apply_value(&args[0], row)),
+        ("count", n) if n >= 2 => Value::count(&args[1..], |row| apply_value(&args[0], row)),
+        ("last-pair", 1) => args[0].last_pair(),
+        ("list-index", n) if n >= 2 => Value::list_index(&args[1..], |row| apply_value(&args[0], row)),
+        ("string-length", 1) => args[0].string_length(),
+        ("string-ref", 2) => args[0].string_ref(&args[1]),
+        ("substring", 2..=3) => args[0].substring(args.get(1), args.get(2)),
+        ("string-copy", 1..=3) => args[0].substring(args.get(1), args.get(2)),
         ("string-append" , _) => Value::string_append(args),
+        ("make-string", 1..=2) => Value::make_string(&args[0], args.get(1)),
+        ("string-set!", 3) => args[0].string_set(&args[1], &args[2]),
+        ("string-fill!", 2..=4) => args[0].string_fill(&args[1], args.get(2), args.get(3)),
+        ("string-copy!", 3..=5) => args[0].string_copy_to(&args[1], &args[2], args.get(3), args.get(4)),
+        ("string=?" , _) => Value::string_compare(&args, false, |ord| ord.is_eq()),
+        ("string<?" , _) => Value::string_compare(&args, false, |ord| ord.is_lt()),
+        ("string>?" , _) => Value::string_compare(&args, false, |ord| ord.is_gt()),
+        ("string<=?", _) => Value::string_compare(&args, false, |ord| ord.is_le()),
+        ("string>=?", _) => Value::string_compare(&args, false, |ord| ord.is_ge()),
+        ("string-ci=?" , _) => Value::string_compare(&args, true, |ord| ord.is_eq()),
+        ("string-ci<?" , _) => Value::string_compare(&args, true, |ord| ord.is_lt()),
+        ("string-ci>?" , _) => Value::string_compare(&args, true, |ord| ord.is_gt()),
+        ("string-ci<=?", _) => Value::string_compare(&args, true, |ord| ord.is_le()),
+        ("string-ci>=?", _) => Value::string_compare(&args, true, |ord| ord.is_ge()),
+        ("string->list", 1..=3) => args[0].string_to_list(args.get(1), args.get(2)),
+        ("list->string", 1) => args[0].list_to_string(),
+        ("string-upcase", 1) => args[0].string_upcase(),
+        ("string-downcase", 1) => args[0].string_downcase(),
+        ("string-contains", 2) => args[0].string_contains(&args[1]),
+        ("string-index", 2) => match &args[1] {
+            Value::Char(c) => args[0].string_index(|elem| Ok(elem == *c)),
+            pred => args[0].string_index(|elem| Ok(apply_value(pred, vec![Value::Char(elem)])?.is_true())),
+        },
+        ("string-join", 1..=2) => args[0].string_join(args.get(1)),
+        ("string-split", 1..=2) => args[0].string_split(args.get(1)),
+        ("string-trim", 1) => args[0].string_trim(true, false),
+        ("string-trim-right", 1) => args[0].string_trim(false, true),
+        ("string-trim-both", 1) => args[0].string_trim(true, true),
+        ("string-pad", 2..=3) => args[0].string_pad(&args[1], args.get(2), false),
+        ("string-pad-right", 2..=3) => args[0].string_pad(&args[1], args.get(2), true),
+        ("string-for-each", n) if n >= 2 => {
+            Value::string_map(&args[1..], |chars| apply_value(&args[0], chars))?;
+            Ok(Unspecified)
+        },
+        ("string-map", n) if n >= 2 => {
+            let chars = Value::string_map(&args[1..], |chars| apply_value(&args[0], chars))?;
+            Value::list(chars).list_to_string()
+        },
         ("symbol->string", 1) => args[0].symbol_to_string(),
         ("string->symbol", 1) => args[0].string_to_symbol(),
-        ("number->string", 1) => args[0].number_to_string(),
-        ("string->number", 1) => args[0].string_to_number(),
+        ("number->string", 1..=2) => args[0].number_to_string(args.get(1)),
+        ("string->number", 1..=2) => args[0].string_to_number(args.get(1)),
+        ("current-input-port" , 0) => Ok(Value::Port(port::current_input())),
+        ("current-output-port", 0) => Ok(Value::Port(port::current_output())),
+        ("current-error-port" , 0) => Ok(Value::Port(port::current_error())),
+        (ident, 1) if ["port?", "input-port?", "output-port?", "binary-port?", "textual-port?"].contains(&ident) => {
+            match (operator, &args[0]) {
+                ("port?", Value::Port(_)) => Ok(Value::Bool(true)),
+                ("binary-port?", Value::Port(port)) => Ok(Value::Bool(port.is_binary())),
+                ("textual-port?", Value::Port(port)) => Ok(Value::Bool(!port.is_binary())),
+                ("input-port?", Value::Port(port)) => Ok(Value::Bool(port.is_input())),
+                ("output-port?", Value::Port(port)) => Ok(Value::Bool(port.is_output())),
+                _ => Ok(Value::Bool(false)),
+            }
+        },
+        ("display", 1..=2) => {
+            output_port(args.get(1))?.write_str(&args[0].display_string())?;
+            Ok(Unspecified)
+        },
+        ("write", 1..=2) => {
+            output_port(args.get(1))?.write_str(&args[0].write_string())?;
+            Ok(Unspecified)
+        },
+        ("write-shared", 1..=2) => {
+            output_port(args.get(1))?.write_str(&Printer::write_shared().print(&args[0]))?;
+            Ok(Unspecified)
+        },
+        ("write-simple", 1..=2) => {
+            output_port(args.get(1))?.write_str(&Printer::write_simple().print(&args[0]))?;
+            Ok(Unspecified)
+        },
+        ("pretty-print", 1..=2) => {
+            output_port(args.get(1))?.write_str(&format!("{}\n", pretty::pretty_print(&args[0])))?;
+            Ok(Unspecified)
+        },
+        ("pretty-print-width", 0) => Ok(Value::Int(pretty::width() as i64)),
+        ("pretty-print-width", 1) => {
+            pretty::set_width(args[0].to_index()?);
+            Ok(Unspecified)
+        },
+        ("format", n) if n >= 1 => {
+            match &args[0] {
+                Value::Str(_) => Value::format(&args[0], &args[1..]),
+                _ if n == 1 => Err(Error::expected("format string", &args[0])),
+                Value::Bool(false) => Value::format(&args[1], &args[2..]),
+                Value::Bool(true) => {
+                    port::current_output().write_str(&Value::format(&args[1], &args[2..])?.as_string()?)?;
+                    Ok(Unspecified)
+                },
+                Value::Port(_) => {
+                    output_port(args.first())?.write_str(&Value::format(&args[1], &args[2..])?.as_string()?)?;
+                    Ok(Unspecified)
+                },
+                dest => Err(Error::expected("format destination", dest)),
+            }
+        },
+        ("write-string", 1..=4) => {
+            let string = args[0].substring(args.get(2), args.get(3))?.as_string()?;
+            output_port(args.get(1))?.write_str(&string)?;
+            Ok(Unspecified)
+        },
+        ("write-char", 1..=2) => {
+            output_port(args.get(1))?.write_str(&args[0].as_char()?.to_string())?;
+            Ok(Unspecified)
+        },
+        ("newline", 0..=1) => {
+            output_port(args.first())?.write_str("\n")?;
+            Ok(Unspecified)
+        },
+        ("read-char", 0..=1) => Ok(input_port(args.first())?.read_char()?.map_or(Value::Eof, Value::Char)),
+        ("peek-char", 0..=1) => Ok(input_port(args.first())?.peek_char()?.map_or(Value::Eof, Value::Char)),
+        ("read-line", 0..=1) => Ok(input_port(args.first())?.read_line()?.map_or(Value::Eof, Value::str)),
+        ("read-string", 1..=2) => Ok(input_port(args.get(1))?.read_string(args[0].to_index()?)?.map_or(Value::Eof, Value::str)),
+        ("char-ready?", 0..=1) => Ok(Value::Bool(input_port(args.first())?.char_ready()?)),
+        ("eof-object", 0) => Ok(Value::Eof),
+        ("eof-object?", 1) => Ok(Value::Bool(matches!(args[0], Value::Eof))),
+        ("close-port" | "close-input-port" | "close-output-port", 1) => {
+            let Value::Port(port) = &args[0] else {
+                return Err(Error::expected("port", &args[0]));
+            };
+            port.close()?;
+            Ok(Unspecified)
+        },
+        ("flush-output-port", 0..=1) => {
+            output_port(args.first())?.flush()?;
+            Ok(Unspecified)
+        },
+        ("open-input-string", 1) => Ok(Value::Port(Port::input_string(&args[0].as_string()?))),
+        ("open-output-string", 0) => Ok(Value::Port(Port::output_string())),
+        ("get-output-string", 1) => {
+            let Value::Port(port) = &args[0] else {
+                return Err(Error::expected("port", &args[0]));
+            };
+            Ok(Value::str(port.get_output_string()?))
+        },
+        ("with-output-to-string", 1) => {
+            let port = Port::output_string();
+            port::with_current_output(port.clone(), || apply_value(&args[0], vec![]))?;
+            Ok(Value::str(port.get_output_string()?))
+        },
+        ("call-with-output-string", 1) => {
+            let port = Port::output_string();
+            apply_value(&args[0], vec![Value::Port(port.clone())])?;
+            Ok(Value::str(port.get_output_string()?))
+        },
+        ("open-input-file", 1) => Ok(Value::Port(Port::input_file(&args[0].as_string()?)?)),
+        ("open-output-file", 1) => Ok(Value::Port(Port::output_file(&args[0].as_string()?)?)),
+        ("call-with-input-file", 2) => {
+            let port = Port::input_file(&args[0].as_string()?)?;
+            let result = apply_value(&args[1], vec![Value::Port(port.clone())]);
+            port.close()?;
+            result
+        },
+        ("call-with-output-file", 2) => {
+            let port = Port::output_file(&args[0].as_string()?)?;
+            let result = apply_value(&args[1], vec![Value::Port(port.clone())]);
+            port.close()?;
+            result
+        },
+        ("with-input-from-file", 2) => {
+            let port = Port::input_file(&args[0].as_string()?)?;
+            let result = port::with_current_input(port.clone(), || apply_value(&args[1], vec![]));
+            port.close()?;
+            result
+        },
+        ("with-output-to-file", 2) => {
+            let port = Port::output_file(&args[0].as_string()?)?;
+            let result = port::with_current_output(port.clone(), || apply_value(&args[1], vec![]));
+            port.close()?;
+            result
+        },
+        ("file-exists?", 1) => Ok(Value::Bool(Path::new(&args[0].as_string()?).exists())),
+        ("delete-file", 1) => {
+            let path = args[0].as_string()?;
+            fs::remove_file(&path).map_err(|err| Error::io(format!("can not delete {:?}: {}", path, err)))?;
+            Ok(Unspecified)
+        },
+        ("make-bytevector", 1..=2) => Value::make_bytevector(&args[0], args.get(1)),
+        ("bytevector", _) => Ok(Value::bytevector(args.iter().map(|byte| byte.to_byte()).collect::<Result<_, _>>()?)),
+        ("bytevector-length", 1) => Ok(Value::Int(args[0].borrow_bytes()?.len() as i64)),
+        ("bytevector-u8-ref", 2) => args[0].bytevector_u8_ref(&args[1]),
+        ("bytevector-u8-set!", 3) => args[0].bytevector_u8_set(&args[1], &args[2]),
+        ("bytevector-copy", 1..=3) => args[0].bytevector_copy(args.get(1), args.get(2)),
+        ("bytevector-copy!", 3..=5) => args[0].bytevector_copy_to(&args[1], &args[2], args.get(3), args.get(4)),
+        ("bytevector-append", _) => Value::bytevector_append(&args),
+        ("utf8->string", 1..=3) => args[0].utf8_to_string(args.get(1), args.get(2)),
+        ("string->utf8", 1..=3) => args[0].string_to_utf8(args.get(1), args.get(2)),
+        ("open-input-bytevector", 1) => Ok(Value::Port(Port::input_bytevector(args[0].as_bytes()?))),
+        ("open-output-bytevector", 0) => Ok(Value::Port(Port::output_bytevector())),
+        ("get-output-bytevector", 1) => {
+            let Value::Port(port) = &args[0] else {
+                return Err(Error::expected("port", &args[0]));
+            };
+            Ok(Value::bytevector(port.get_output_bytes()?))
+        },
+        ("read-u8", 0..=1) => Ok(input_port(args.first())?.read_u8()?.map_or(Value::Eof, |byte| Value::Int(byte as i64))),
+        ("peek-u8", 0..=1) => Ok(input_port(args.first())?.peek_u8()?.map_or(Value::Eof, |byte| Value::Int(byte as i64))),
+        ("u8-ready?", 0..=1) => Ok(Value::Bool(input_port(args.first())?.u8_ready()?)),
+        ("read-bytevector", 1..=2) => Ok(input_port(args.get(1))?.read_bytes(args[0].to_index()?)?.map_or(Value::Eof, Value::bytevector)),
+        ("write-u8", 1..=2) => {
+            output_port(args.get(1))?.write_bytes(&[args[0].to_byte()?])?;
+            Ok(Unspecified)
+        },
+        ("write-bytevector", 1..=4) => {
+            let bytes = args[0].bytevector_copy(args.get(2), args.get(3))?.as_bytes()?;
+            output_port(args.get(1))?.write_bytes(&bytes)?;
+            Ok(Unspecified)
+        },
+        ("read", 0..=1) => {
+            // positions in the text read are meaningless to the caller
+            let datum = input_port(args.first())?.read_datum().map_err(|err| Error { span: None, ..err })?;
+            Ok(datum.unwrap_or(Value::Eof))
+        },
         ("print", _) => {
+            let port = port::current_output();
             for arg in args {
-                print!("{} ", arg);
+                port.write_str(&format!("{} ", arg.display_string()))?;
             }
-            println!("");
-            Ok(Value::Nil)
+            port.write_str("\n")?;
+            Ok(Unspecified)
+        },
+        ("command-line", 0) => Ok(Value::list(COMMAND_LINE.with(|command_line| command_line.borrow().iter().cloned().map(Value::str).collect()))),
+        // unwinds like an error so that `dynamic-wind` after-thunks run, and main exits with the status
+        ("exit", 0..=1) => Err(Error::new(ErrorKind::Exit(exit_status(args.first())?))),
+        ("emergency-exit", 0..=1) => process::exit(exit_status(args.first())?),
+        ("get-environment-variable", 1) => match env::var(args[0].as_string()?) {
+            Ok(value) => Ok(Value::str(value)),
+            Err(_) => Ok(Value::Bool(false)),
         },
-        (_, n) => Err(format!("the number of argments is not {n}")),
+        ("get-environment-variables", 0) => Ok(Value::list(env::vars().map(|(name, value)| {
+            Pair(Rc::new(RefCell::new(Cons(Value::str(name), Value::str(value)))))
+        }).collect())),
+        ("current-second", 0) => Ok(Value::Num(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |time| time.as_secs_f64()))),
+        ("current-jiffy", 0) => Ok(Value::Int(EPOCH.with(|epoch| epoch.elapsed().as_micros()) as i64)),
+        ("jiffies-per-second", 0) => Ok(Value::Int(1_000_000)),
+        (_, n) => Err(Error::new(ErrorKind::Arity { expected: None, actual: n })),
+    }
+}
+
+// #t or no status is success and #f a general failure
+fn exit_status(status: Option<&Value>) -> Result<i32, Error> {
+    match status {
+        None | Some(Value::Bool(true)) => Ok(0),
+        Some(Value::Bool(false)) => Ok(1),
+        // the process status is a byte
+        Some(Value::Int(status)) => u8::try_from(*status).map(i32::from).map_err(|_| Error::range(format!("exit status {} is out of range", status))),
+        Some(value) => Err(Error::expected("integer or boolean", value)),
+    }
+}
+
+fn input_port(port: Option<&Value>) -> Result<Port, Error> {
+    match port {
+        Some(Value::Port(port)) if port.is_input() => Ok(port.clone()),
+        Some(value) => Err(Error::expected("input port", value)),
+        None => Ok(port::current_input()),
+    }
+}
+
+fn output_port(port: Option<&Value>) -> Result<Port, Error> {
+    match port {
+        Some(Value::Port(port)) if port.is_output() => Ok(port.clone()),
+        Some(value) => Err(Error::expected("output port", value)),
+        None => Ok(port::current_output()),
     }
 }
@@ -1,8 +1,11 @@
-use crate::data::Token;
+use crate::data::{Token, Value, Source, Span, CHAR_NAMES};
+use crate::error::{Error, ErrorKind};
+
+use std::rc::Rc;
 
 use Token::*;
 
-const KEYWORDS: [&str; 13] = [
+pub const KEYWORDS: [&str; 14] = [
     "define",
     "lambda",
     "let", "let*", "letrec",
@@ -12,85 +15,209 @@ const KEYWORDS: [&str; 13] = [
     "do",
     "and", "or",
     "quote",
+    "guard",
 ];
-const OPERATORS: [&str; 38] = [
-    "eq?", "neq?", "equal?",
-    "list?", "pair?", "procedure?", "symbol?", "number?", "boolean?", "string?", "null?", "procedure?",
-    "list", "length", "memq", "last", "append",
-    "cons", "car", "cdr", "set-car!", "set-cdr!",
-    "not",
-    "=", "<=", "<", ">=", ">", "+", "-", "*", "/",
-    "string-append", "symbol->string", "string->symbol", "number->string", "string->number",
-    "print",
-];
 
-pub fn tokenize(code: &str) -> Result<Vec<Token>, String> {
-    let mut lexer = Lexer::new(code);
+pub type Tokens<'a> = Vec<(Token<'a>, Span)>;
+
+pub fn tokenize(source: &Rc<Source>) -> Result<Tokens<'_>, Error> {
+    let mut lexer = Lexer::new(source);
     lexer.tokenize()
 }
 
-#[derive(Debug)]
+// every token, with an `Invalid` one in place of each that has an error
+pub fn tokenize_recovering(source: &Rc<Source>) -> (Tokens<'_>, Vec<Error>) {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(err) => {
+                let start = err.span.as_ref().map_or(0, |span| span.offset);
+                let mut end = source.code.len() - lexer.chs.len();
+                // an unknown word is left unread
+                if end == start {
+                    let word = lexer.chs.split(|c: char| c.is_whitespace() || "()".contains(c)).next().unwrap_or("");
+                    end += word.len().max(lexer.peek_char().map_or(0, char::len_utf8));
+                    lexer.chs = &source.code[end..];
+                }
+                tokens.push((Invalid(&source.code[start..end]), Span { source: Rc::clone(source), offset: start }));
+                errors.push(err);
+            },
+        }
+    }
+    (tokens, errors)
+}
+
+// whether `source` stops inside a datum, in an open list, string or block comment or after a quote,
+// so that more input would complete it
+pub fn is_incomplete(source: &Rc<Source>) -> bool {
+    let mut lexer = Lexer::new(source);
+    match lexer.tokenize() {
+        // running out of input is the only error leaving nothing unread
+        Err(_) => lexer.chs.is_empty(),
+        Ok(tokens) => {
+            let mut depth = 0;
+            for (token, _) in &tokens {
+                match token {
+                    OpenParen | OpenByteVector => depth += 1,
+                    CloseParen => depth -= 1,
+                    _ => {},
+                }
+            }
+            depth > 0 || matches!(tokens.last(), Some((SingleQuote | DatumLabel(_), _)))
+        },
+    }
+}
+
+// the tokens of the first datum in `source` and the offset just past it,
+// or None when `source` ends before the datum does so that more input is needed
+pub fn datum_tokens(source: &Rc<Source>) -> Result<Option<(Tokens<'_>, usize)>, Error> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    while let Some(token) = lexer.next_token() {
+        let (token, span) = match token {
+            Ok(token) => token,
+            Err(_) if lexer.chs.is_empty() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        match token {
+            OpenParen | OpenByteVector => depth += 1,
+            CloseParen => depth -= 1,
+            _ => {},
+        }
+        let prefix = matches!(token, SingleQuote | DatumLabel(_));
+        tokens.push((token, span));
+        if depth <= 0 && !prefix {
+            return Ok(Some((tokens, source.code.len() - lexer.chs.len())));
+        }
+    }
+    Ok(None)
+}
+
 struct Lexer<'a> {
+    source: &'a Rc<Source>,
     chs: &'a str,
 }
 
 impl<'a> Lexer<'a> {
-    fn new(code: &'a str) -> Self {
-        Lexer { chs: code }
+    fn new(source: &'a Rc<Source>) -> Self {
+        Lexer { source, chs: &source.code }
     }
 
-    fn tokenize(&mut self) -> Result<Vec<Token<'a>>, String> {
+    fn tokenize(&mut self) -> Result<Vec<(Token<'a>, Span)>, Error> {
         let mut tokens = Vec::new();
+        while let Some(token) = self.next_token() {
+            tokens.push(token?);
+        }
+        Ok(tokens)
+    }
+
+    fn next_token(&mut self) -> Option<Result<(Token<'a>, Span), Error>> {
         while let Some(c) = self.peek_char() {
             if c.is_whitespace() {
                 self.next_char();
                 continue;
             }
 
-            if c == '"' {
-                tokens.push(self.read_string()?);
+            if c == ';' {
+                self.chs = self.chs.find('\n').map_or("", |i| &self.chs[i..]);
                 continue;
             }
 
-            if self.next_if("(") {
-                tokens.push(OpenParen);
+            let span = Span { source: Rc::clone(self.source), offset: self.source.code.len() - self.chs.len() };
+            if self.next_if("#|") {
+                if let Err(err) = self.skip_block_comment() {
+                    return Some(Err(Error::new(ErrorKind::Lex(err)).at(&span)));
+                }
                 continue;
             }
 
-            if self.next_if(")") {
-                tokens.push(CloseParen);
-                continue;
-            }
+            return Some(match self.read_token(c) {
+                Ok(token) => Ok((token, span)),
+                Err(err) => Err(Error::new(ErrorKind::Lex(err)).at(&span)),
+            });
+        }
+        None
+    }
 
-            if self.next_if("'") {
-                tokens.push(SingleQuote);
-                continue;
-            }
+    fn read_token(&mut self, c: char) -> Result<Token<'a>, String> {
+        if c == '"' {
+            return self.read_string();
+        }
 
-            if c.is_ascii_digit() {
-                tokens.push(self.read_num());
-                continue;
-            }
+        if let Some(label) = self.read_datum_label() {
+            return Ok(label);
+        }
 
-            if is_ident_char(c) {
-                tokens.push(self.read_keyword_ident()?);
-                continue;
-            }
+        if self.next_if("#u8(") {
+            return Ok(OpenByteVector);
+        }
 
-            if self.next_if("#t") {
-                tokens.push(Bool(true));
-                continue;
-            }
+        if self.next_if("(") {
+            return Ok(OpenParen);
+        }
 
-            if self.next_if("#f") {
-                tokens.push(Bool(false));
-                continue;
-            }
+        if self.next_if(")") {
+            return Ok(CloseParen);
+        }
 
-            return Err(format!("invalid token '{}'", self.chs));
+        if self.next_if("'") {
+            return Ok(SingleQuote);
         }
 
-        Ok(tokens)
+        if c.is_ascii_digit() || c == '#' && self.chs[1..].starts_with(|c| "xXbBoOdDeEiI".contains(c)) {
+            return self.read_num();
+        }
+
+        if is_ident_char(c) {
+            return self.read_keyword_ident();
+        }
+
+        if self.next_if("#\\") {
+            return self.read_char();
+        }
+
+        if self.next_if("#t") {
+            return Ok(Bool(true));
+        }
+
+        if self.next_if("#f") {
+            return Ok(Bool(false));
+        }
+
+        let word = self.chs.split(|c: char| c.is_whitespace() || "()".contains(c)).next().unwrap_or(self.chs);
+        Err(format!("invalid token '{}'", word))
+    }
+
+    // `#n=` or `#n#`
+    fn read_datum_label(&mut self) -> Option<Token<'a>> {
+        let rest = self.chs.strip_prefix('#')?;
+        let len = rest.find(|c: char| !c.is_ascii_digit())?;
+        let label = rest[..len].parse().ok()?;
+        let token = match rest[len..].chars().next()? {
+            '=' => DatumLabel(label),
+            '#' => DatumRef(label),
+            _ => return None,
+        };
+        self.chs = &rest[len + 1..];
+        Some(token)
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), String> {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.next_if("#|") {
+                depth += 1;
+            } else if self.next_if("|#") {
+                depth -= 1;
+            } else if self.next_char().is_none() {
+                return Err(String::from("expect '|#'"));
+            }
+        }
+        Ok(())
     }
 
     fn read_keyword_ident(&mut self) -> Result<Token<'a>, String> {
@@ -104,10 +231,10 @@ impl<'a> Lexer<'a> {
             }
         };
 
-        if let Some(keyword) = KEYWORDS.iter().find(|&&keyword| keyword == prefix) {
+        if let Some(num) = num_token(prefix) {
+            Ok(num)
+        } else if let Some(keyword) = KEYWORDS.iter().find(|&&keyword| keyword == prefix) {
             Ok(Keyword(keyword))
-        } else if let Some(operator) = OPERATORS.iter().find(|&&operator| operator == prefix) {
-            Ok(Operator(operator))
         } else {
             Ok(Ident(prefix))
         }
@@ -117,27 +244,73 @@ impl<'a> Lexer<'a> {
         self.next_char();
 
         let mut s = String::new();
+        // a bad escape is reported once the whole string is read
+        let mut err = None;
         while let Some(c) = self.next_char() {
             match c {
-                '"' => return Ok(Str(s)),
-                '\\' => s.push(self.next_char().unwrap()),
+                '"' => return err.map_or(Ok(Str(s)), Err),
+                '\\' => match self.read_escape() {
+                    Ok(c) => s.push(c),
+                    Err(escape) => {
+                        err.get_or_insert(escape);
+                    },
+                },
                 c => s.push(c),
             }
         }
         Err(String::from("expect '\"'"))
     }
 
-    fn read_num(&mut self) -> Token<'a> {
-        let mut val = 0;
+    fn read_escape(&mut self) -> Result<char, String> {
+        match self.next_char() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('a') => Ok('\x07'),
+            Some('b') => Ok('\x08'),
+            Some('0') => Ok('\0'),
+            Some('x') => {
+                let (hex, rest) = self.chs.split_once(';').ok_or("expect ';'")?;
+                self.chs = rest;
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).ok_or(format!("invalid escape '\\x{};'", hex))
+            },
+            Some(c) => Ok(c),
+            None => Err(String::from("expect '\"'")),
+        }
+    }
+
+    fn read_char(&mut self) -> Result<Token<'a>, String> {
+        let Some(c) = self.next_char() else {
+            return Err(String::from("expect character"));
+        };
+        if !c.is_ascii_alphabetic() || !self.peek_char().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            return Ok(Char(c));
+        }
+
+        let mut name = String::from(c);
         while let Some(c) = self.peek_char() {
-            if let Some(d) = c.to_digit(10) {
-                val = 10 * val + d;
+            if c.is_ascii_alphanumeric() {
+                name.push(c);
                 self.next_char();
             } else {
                 break;
             }
         }
-        Num(val as f32)
+
+        if let Some((_, c)) = CHAR_NAMES.iter().find(|(named, _)| *named == name) {
+            Ok(Char(*c))
+        } else if let Some(c) = name.strip_prefix('x').and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(char::from_u32) {
+            Ok(Char(c))
+        } else {
+            Err(format!("invalid character name '{}'", name))
+        }
+    }
+
+    fn read_num(&mut self) -> Result<Token<'a>, String> {
+        let len = self.chs.find(|c| !is_ident_char(c) && c != '#').unwrap_or(self.chs.len());
+        let literal;
+        (literal, self.chs) = self.chs.split_at(len);
+        num_token(literal).ok_or(format!("invalid number '{}'", literal))
     }
 
     fn next_if(&mut self, expected: &str) -> bool {
@@ -164,6 +337,14 @@ impl<'a> Lexer<'a> {
     }
 }
 
-fn is_ident_char(c: char) -> bool {
+fn num_token(literal: &str) -> Option<Token<'_>> {
+    match Value::parse_number(literal, 10)? {
+        Value::Int(val) => Some(Int(val)),
+        Value::Num(val) => Some(Num(val)),
+        _ => None,
+    }
+}
+
+pub fn is_ident_char(c: char) -> bool {
     c.is_ascii_alphanumeric() || "!$%&*+-./<=>?@^_".contains(c)
 }
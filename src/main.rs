@@ -1,64 +1,340 @@
 mod data;
+mod error;
 mod lexer;
 mod parser;
 mod exec;
+mod port;
+mod pretty;
+mod editor;
+#[cfg(test)]
+mod tests;
 
-use std::io::{stdin, stdout, Write};
 use std::env;
 use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+use std::rc::Rc;
+use std::time::Instant;
 
-use crate::data::Env;
-use crate::lexer::tokenize;
+use rustyline::Editor;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+
+use crate::data::{Env, Source, Value, Proc};
+use crate::error::{Error, ErrorKind};
+use crate::editor::ReplHelper;
+use crate::lexer::{is_incomplete, KEYWORDS};
 use crate::parser::parse;
-use crate::exec::{exec, exec_line};
+use crate::exec::{exec, exec_line, steps, set_command_line};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        match repl() {
-            Ok(()) => {},
-            Err(err) => eprintln!("{err}"),
+// how running a program failed, each with its own exit status after sysexits.h
+enum Failure {
+    Usage(String),
+    Syntax(Vec<Error>),
+    Runtime(Error),
+    IO(Error),
+    // the program called `exit`
+    Exit(i32),
+}
+
+impl Failure {
+    fn runtime(err: Error) -> Self {
+        match err.kind {
+            ErrorKind::Exit(status) => Failure::Exit(status),
+            _ => Failure::Runtime(err),
         }
-    } else {
-        match interprete(&args[1]) {
-            Ok(()) => {},
-            Err(err) => eprintln!("{err}"),
+    }
+
+    fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage(_) => 64,
+            Failure::Syntax(_) => 65,
+            Failure::Runtime(_) => 70,
+            Failure::IO(_) => 74,
+            Failure::Exit(status) => *status,
         }
     }
+
+    fn report(&self) {
+        match self {
+            Failure::Usage(usage) => eprintln!("{usage}"),
+            Failure::Syntax(errors) => report(errors),
+            Failure::Runtime(err) | Failure::IO(err) => eprintln!("{err}"),
+            Failure::Exit(_) => {},
+        }
+    }
+}
+
+const USAGE: &str = "\
+usage: scheme-processor [option ...] [file | -] [arg ...]
+  -e EXPR    evaluate EXPR
+  -l FILE    load FILE
+  -i         start the repl after running the program
+  -          read the program from standard input
+  --         pass the remaining arguments to the program
+  --help     show this help
+  --version  show the version
+options are processed in order, and the repl starts when neither a program nor -e is given";
+
+// what to run before the program, in command line order
+enum Preload {
+    Eval(String),
+    Load(String),
 }
 
-fn interprete(file: &str) -> Result<(), String> {
-    let code = &fs::read_to_string(file).expect("file not found");
-    let tokens = tokenize(code)?;
-    let nodes = parse(tokens)?;
-    exec(nodes)
+struct Options {
+    preloads: Vec<Preload>,
+    // a file name, or `-` for standard input
+    program: Option<String>,
+    args: Vec<String>,
+    interactive: bool,
 }
 
-fn repl() -> Result<(), String> {
-    let mut code;
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, Failure> {
+        let mut options = Options { preloads: Vec::new(), program: None, args: Vec::new(), interactive: false };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" => {
+                    println!("{USAGE}");
+                    process::exit(0);
+                },
+                "--version" => {
+                    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+                    process::exit(0);
+                },
+                "-e" | "-l" => {
+                    let value = args.next().ok_or_else(|| Failure::Usage(format!("option {} needs an argument, try --help", arg)))?;
+                    options.preloads.push(if arg == "-e" { Preload::Eval(value) } else { Preload::Load(value) });
+                },
+                "-i" => options.interactive = true,
+                "--" => break,
+                option if option.starts_with('-') && option != "-" => return Err(Failure::Usage(format!("unknown option {}, try --help", option))),
+                _ => {
+                    options.program = Some(arg);
+                    break;
+                },
+            }
+        }
+        // a `--` may also separate the program from its arguments
+        options.args = args.skip_while(|arg| arg == "--").collect();
+        Ok(options)
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let name = args.next().unwrap_or_else(|| String::from(env!("CARGO_PKG_NAME")));
+    let result = Options::parse(args).and_then(|options| run(name, options));
+    // ports kept alive by reference cycles are never dropped, and `process::exit` skips the flush that would happen on return
+    let _ = port::flush_all();
+    if let Err(failure) = result {
+        failure.report();
+        process::exit(failure.exit_code());
+    }
+}
+
+fn report(errors: &[Error]) {
+    for err in errors {
+        eprintln!("{err}");
+    }
+}
+
+fn run(name: String, options: Options) -> Result<(), Failure> {
+    let program = options.program.clone().unwrap_or(name);
+    set_command_line([vec![program], options.args].concat());
+
     let mut env = Env::new();
+    for preload in &options.preloads {
+        match preload {
+            Preload::Eval(expr) => interprete(Source::new("-e", expr.clone()), &mut env)?,
+            Preload::Load(file) => interprete(read_file(file)?, &mut env)?,
+        }
+    }
+    match options.program.as_deref() {
+        Some("-") => {
+            let mut code = String::new();
+            io::stdin().read_to_string(&mut code).map_err(|err| Failure::IO(Error::io(format!("can not read standard input: {}", err))))?;
+            interprete(Source::new("stdin", code), &mut env)?;
+        },
+        Some(file) => interprete(read_file(file)?, &mut env)?,
+        None => {},
+    }
+
+    if options.interactive || options.program.is_none() && options.preloads.iter().all(|preload| matches!(preload, Preload::Load(_))) {
+        repl(env)?;
+    }
+    Ok(())
+}
+
+fn read_file(file: &str) -> Result<Rc<Source>, Failure> {
+    let code = fs::read_to_string(file).map_err(|err| Failure::IO(Error::io(format!("can not open {:?}: {}", file, err))))?;
+    Ok(Source::new(file, code))
+}
+
+fn interprete(source: Rc<Source>, env: &mut Env) -> Result<(), Failure> {
+    let nodes = parse(&source).map_err(Failure::Syntax)?;
+    exec(nodes, env).map_err(Failure::runtime)
+}
+
+fn repl(mut env: Env) -> Result<(), Failure> {
+    // results bound so far, as $1, $2, ...
+    let mut results = 0;
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new().map_err(|err| Failure::IO(Error::io(err.to_string())))?;
+    editor.set_helper(Some(ReplHelper::new(env.clone())));
+    let history = env::var_os("HOME").map(|home| Path::new(&home).join(".scheme_history"));
+    if let Some(history) = &history {
+        // there is no history yet on the first run
+        let _ = editor.load_history(history);
+    }
+
+    // lines of a datum still being typed
+    let mut pending = String::new();
+    let mut result = Ok(());
     loop {
-        print!("> ");
-        stdout().flush().unwrap();
-
-        code = String::new();
-        match stdin().read_line(&mut code) {
-            Ok(0) => break Ok(()),
-            Ok(_) => {
-                let tokens = tokenize(&code)?;
-                if tokens.len() == 0 {
+        let prompt = if pending.is_empty() { "> " } else { "... " };
+        match editor.readline(prompt) {
+            Ok(line) if pending.is_empty() && line.trim_start().starts_with(',') => {
+                let _ = editor.add_history_entry(line.trim());
+                match meta_command(line.trim_start(), &mut env, &mut results, &mut editor) {
+                    Ok(true) => {},
+                    Ok(false) => break,
+                    Err(failure) => {
+                        result = Err(failure);
+                        break;
+                    },
+                }
+            },
+            Ok(line) => {
+                pending.push_str(&line);
+                pending.push('\n');
+                let source = Source::new("repl", pending.clone());
+                if is_incomplete(&source) {
                     continue;
-                } else {
-                    let nodes = parse(tokens)?;
-                    for node in nodes {
-                        match exec_line(node, &mut env) {
-                            Ok(()) => {},
-                            Err(err) => eprintln!("{err}"),
-                        }
-                    }
                 }
+                let _ = editor.add_history_entry(pending.trim_end());
+                pending.clear();
+                if let Err(failure) = eval_source(&source, &mut env, &mut results) {
+                    result = Err(failure);
+                    break;
+                }
+            },
+            // Ctrl-C drops what was typed so far
+            Err(ReadlineError::Interrupted) => pending.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                result = Err(Failure::IO(Error::io(err.to_string())));
+                break;
             },
-            Err(err) => eprintln!("{err}"),
         }
     }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(|err| Failure::IO(Error::io(format!("can not save history: {}", err))))?;
+    }
+    result
+}
+
+// evaluate every form in `source`, binding and printing results and reporting errors as they come
+fn eval_source(source: &Rc<Source>, env: &mut Env, results: &mut usize) -> Result<(), Failure> {
+    let nodes = match parse(source) {
+        Ok(nodes) => nodes,
+        Err(errors) => {
+            report(&errors);
+            return Ok(());
+        },
+    };
+    for node in nodes {
+        let result = exec_line(node, env);
+        port::finish_stdout_line().map_err(Failure::IO)?;
+        match result {
+            Ok(Some(value)) => {
+                *results += 1;
+                let ident = format!("${}", results);
+                let printed = if pretty::enabled() { pretty::pretty_print(&value) } else { value.write_string() };
+                println!("{} = {}", ident, printed);
+                env.add(&ident, value);
+            },
+            Ok(None) => {},
+            Err(err) => match Failure::runtime(err) {
+                Failure::Runtime(err) => eprintln!("{err}"),
+                failure => return Err(failure),
+            },
+        }
+    }
+    Ok(())
+}
+
+const META_COMMANDS: [(&str, &str); 9] = [
+    (",help", "show this list"),
+    (",load FILE", "evaluate FILE into the current environment"),
+    (",env", "list every binding"),
+    (",time EXPR", "evaluate EXPR and report the time and steps it took"),
+    (",expand EXPR", "show EXPR with derived syntax expanded"),
+    (",describe SYMBOL", "show what SYMBOL is bound to"),
+    (",pretty on|off", "lay out results over lines or write each on one line"),
+    (",reset", "forget every definition"),
+    (",quit", "leave the repl"),
+];
+
+// run a `,command` line, returning false once the repl should stop
+fn meta_command(line: &str, env: &mut Env, results: &mut usize, editor: &mut Editor<ReplHelper, DefaultHistory>) -> Result<bool, Failure> {
+    let line = line[1..].trim();
+    let (command, arg) = line.split_once(char::is_whitespace).map_or((line, ""), |(command, arg)| (command, arg.trim()));
+    match (command, arg) {
+        ("help", "") => {
+            for (usage, description) in META_COMMANDS {
+                println!("{:<18}{}", usage, description);
+            }
+        },
+        ("load", file) if !file.is_empty() => match fs::read_to_string(file) {
+            Ok(code) => eval_source(&Source::new(file, code), env, results)?,
+            Err(err) => eprintln!("{}", Error::io(format!("can not open {:?}: {}", file, err))),
+        },
+        ("env", "") => print!("{env}"),
+        ("time", expr) if !expr.is_empty() => {
+            let (start, start_steps) = (Instant::now(), steps());
+            eval_source(&Source::new("repl", expr.to_string()), env, results)?;
+            println!("; {:?}, {} steps", start.elapsed(), steps() - start_steps);
+        },
+        ("expand", expr) if !expr.is_empty() => {
+            let source = Source::new("repl", expr.to_string());
+            match parse(&source) {
+                Ok(nodes) => {
+                    for node in nodes {
+                        println!("{}", pretty::pretty_print(&node.to_datum()));
+                    }
+                },
+                Err(errors) => report(&errors),
+            }
+        },
+        ("describe", ident) if !ident.is_empty() => {
+            if KEYWORDS.contains(&ident) {
+                println!("{ident} is a special form");
+            } else {
+                match env.find(&ident.to_string()) {
+                    Ok(Value::Proc(Proc::Lambda { params, .. })) => println!("{ident} is a procedure taking ({})", params.join(" ")),
+                    Ok(Value::Proc(Proc::Opr(_))) => println!("{ident} is a built-in procedure"),
+                    Ok(value) => println!("{ident} is bound to {value}"),
+                    Err(err) => eprintln!("{err}"),
+                }
+            }
+        },
+        ("pretty", "on") => pretty::set_enabled(true),
+        ("pretty", "off") => pretty::set_enabled(false),
+        ("reset", "") => {
+            *env = Env::new();
+            *results = 0;
+            if let Some(helper) = editor.helper_mut() {
+                helper.env = env.clone();
+            }
+        },
+        ("quit", "") => return Ok(false),
+        _ => match META_COMMANDS.iter().find(|(usage, _)| usage[1..].split(' ').next() == Some(command)) {
+            Some((usage, _)) => eprintln!("usage: {usage}"),
+            None => eprintln!("unknown command ,{command}, try ,help"),
+        },
+    }
+    Ok(true)
 }
@@ -1,109 +1,170 @@
-use crate::data::{Token, TopLevel, Body, Defn, Expr, Value};
+use crate::data::{Token, TopLevel, Body, Defn, Expr, Value, Cons, Source, Span};
+use crate::error::Error;
+use crate::lexer::tokenize_recovering;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
 use Token::*;
 use Expr::*;
 use Value::*;
 
-pub fn parse<'a>(tokens: Vec<Token<'a>>) -> Result<Vec<TopLevel>, String> {
+// every syntax error in the program, lexical or not, in the order they appear
+pub fn parse(source: &Rc<Source>) -> Result<Vec<TopLevel>, Vec<Error>> {
+    let (tokens, mut errors) = tokenize_recovering(source);
     let mut parser = Parser::new(tokens);
-    parser.parse()
+    match parser.parse() {
+        Ok(nodes) if errors.is_empty() => Ok(nodes),
+        Ok(_) => Err(errors),
+        Err(parse_errors) => {
+            // a parse error where a token could not be read only repeats the lexer
+            let offset = |err: &Error| err.span.as_ref().map(|span| span.offset);
+            let parse_errors: Vec<_> = parse_errors.into_iter().filter(|err| errors.iter().all(|lex_err| offset(lex_err) != offset(err))).collect();
+            errors.extend(parse_errors);
+            errors.sort_by_key(offset);
+            Err(errors)
+        },
+    }
+}
+
+pub fn parse_datum<'a>(tokens: Vec<(Token<'a>, Span)>) -> Result<Value, Error> {
+    let mut parser = Parser::new(tokens);
+    let datum = parser.parse_s_expr()?;
+    match parser.peek_token() {
+        Ok(token) => Err(parser.error(parser.idx, format!("unexpected '{}'", token))),
+        Err(_) => Ok(datum),
+    }
 }
 
 struct Parser<'a> {
-    tokens: Vec<Token<'a>>,
+    tokens: Vec<(Token<'a>, Span)>,
     idx: usize,
+    labels: HashMap<u32, Value>,
 }
 
 impl<'a> Parser<'a> {
-    fn new(tokens: Vec<Token<'a>>) -> Self {
+    fn new(tokens: Vec<(Token<'a>, Span)>) -> Self {
         Parser {
             tokens,
             idx: 0,
+            labels: HashMap::new(),
         }
     }
 
-    fn parse(&mut self) -> Result<Vec<TopLevel>, String> {
+    fn parse(&mut self) -> Result<Vec<TopLevel>, Vec<Error>> {
         let mut vec = Vec::new();
+        let mut errors = Vec::new();
         while self.idx < self.tokens.len() {
-            vec.push(self.parse_toplevel()?);
+            let start = self.idx;
+            match self.parse_toplevel() {
+                Ok(node) => vec.push(node),
+                Err(err) => {
+                    errors.push(err);
+                    self.skip_form(start);
+                },
+            }
+        }
+        if errors.is_empty() { Ok(vec) } else { Err(errors) }
+    }
+
+    // moves past the malformed top-level form at `start`, up to its closing paren or, if it is left
+    // unclosed, up to the next open paren at the start of a line
+    fn skip_form(&mut self, start: usize) {
+        self.idx = start;
+        let mut depth = 0;
+        while let Some((token, span)) = self.tokens.get(self.idx) {
+            if self.idx > start && depth > 0 && *token == OpenParen && span.starts_line() {
+                break;
+            }
+            self.idx += 1;
+            match token {
+                OpenParen | OpenByteVector => depth += 1,
+                CloseParen => depth -= 1,
+                SingleQuote | DatumLabel(_) => continue,
+                _ => {},
+            }
+            if depth <= 0 {
+                break;
+            }
         }
-        Ok(vec)
     }
 
-    fn parse_toplevel(&mut self) -> Result<TopLevel, String> {
-        Ok(if let Ok(defn) = self.parse_defn() {
-            TopLevel::Defn(defn)
+    fn parse_toplevel(&mut self) -> Result<TopLevel, Error> {
+        if self.peek_defn() {
+            Ok(TopLevel::Defn(self.parse_defn()?))
         } else {
-            let expr = self.parse_expr()?;
-            TopLevel::Expr(expr)
-        })
+            Ok(TopLevel::Expr(self.parse_expr()?))
+        }
     }
 
-    fn parse_body(&mut self) -> Result<Body, String> {
+    fn parse_body(&mut self) -> Result<Body, Error> {
         let mut defns = Vec::new();
         let mut exprs = Vec::new();
-        while let Ok(defn) = self.parse_defn() {
-            defns.push(defn);
+        while self.peek_defn() {
+            defns.push(self.parse_defn()?);
         }
-        while let Ok(expr) = self.parse_expr() {
-            exprs.push(expr);
+        while self.idx < self.tokens.len() && !self.peek_if(CloseParen) {
+            exprs.push(self.parse_expr()?);
         }
-        self.idx -= 1;
 
         Ok(Body { defns, exprs })
     }
 
-    fn parse_defn(&mut self) -> Result<Defn, String> {
+    fn peek_defn(&self) -> bool {
+        self.peek_if(OpenParen) && self.tokens.get(self.idx + 1).is_some_and(|(token, _)| *token == Keyword("define"))
+    }
+
+    fn parse_defn(&mut self) -> Result<Defn, Error> {
+        let span = self.tokens[self.idx].1.clone();
+        self.next_force(OpenParen)?;
+        self.next_force(Keyword("define"))?;
         if self.next_if(OpenParen) {
-            if self.next_if(Keyword("define")) {
-                if self.next_if(OpenParen) {
-                    let ident = self.next_ident()?;
+            let ident = self.next_ident()?;
 
-                    let mut params = Vec::new();
-                    while !self.next_if(CloseParen) {
-                        let param = self.next_ident()?;
-                        params.push(param);
-                    }
+            let mut params = Vec::new();
+            while !self.next_if(CloseParen) {
+                let param = self.next_ident()?;
+                params.push(param);
+            }
 
-                    let body = self.parse_body()?;
+            let body = self.parse_body()?;
 
-                    self.next_force(CloseParen)?;
+            self.next_force(CloseParen)?;
 
-                    return Ok(Defn { ident, expr: Lambda { params, body }})
-                } else {
-                    let ident = self.next_ident()?;
-                    let expr = self.parse_expr()?;
-                    self.next_force(CloseParen)?;
+            Ok(Defn { ident, expr: Lambda { params, body, span }})
+        } else {
+            let ident = self.next_ident()?;
+            let expr = self.parse_expr()?;
+            self.next_force(CloseParen)?;
 
-                    return Ok(Defn { ident, expr });
-                }
-            }
-            self.idx -= 1;
+            Ok(Defn { ident, expr })
         }
-        Err(String::from(""))
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, String> {
-        match self.next_token()? {
+    fn parse_expr(&mut self) -> Result<Expr, Error> {
+        let token = self.next_token()?;
+        let span = self.tokens[self.idx - 1].1.clone();
+        match token {
             OpenParen => {
-                let expr = self.parse_apply()?;
+                let expr = self.parse_apply(span)?;
                 self.next_force(CloseParen)?;
                 Ok(expr)
             },
-            SingleQuote => Ok(Quote(Box::new(self.parse_s_expr()?))),
-            Operator(operator) => Ok(Opr(operator)),
-            Token::Ident(ident) => Ok(Var(ident.to_string())),
-            Token::Num(val) => Ok(Expr::Num(val)),
-            Token::Bool(val) => Ok(Expr::Bool(val)),
-            Token::Str(val) => Ok(Expr::Str(val)),
-            token => Err(format!("unexpected token {:?}", token)),
+            SingleQuote => Ok(Quote(Box::new(self.parse_s_expr()?), span)),
+            OpenByteVector => Ok(Quote(Box::new(self.parse_bytevector()?), span)),
+            Token::Ident(ident) => Ok(Var(ident.to_string(), span)),
+            Token::Int(val) => Ok(Expr::Int(val, span)),
+            Token::Num(val) => Ok(Expr::Num(val, span)),
+            Token::Bool(val) => Ok(Expr::Bool(val, span)),
+            Token::Char(val) => Ok(Expr::Char(val, span)),
+            Token::Str(val) => Ok(Expr::Str(val, span)),
+            Invalid(_) => Ok(Expr::Nil(span)),
+            token => Err(self.error(self.idx - 1, format!("unexpected '{}'", token))),
         }
     }
 
-    fn parse_apply(&mut self) -> Result<Expr, String> {
+    fn parse_apply(&mut self, span: Span) -> Result<Expr, Error> {
         if let Ok(keyword) = self.next_keyword() {
             match keyword {
                 "lambda" => {
@@ -116,7 +177,7 @@ impl<'a> Parser<'a> {
 
                     let body = self.parse_body()?;
 
-                    Ok(Lambda { params, body })
+                    Ok(Lambda { params, body, span })
                 },
                 s if s == "let" || s == "let*" || s == "letrec" => {
                     let mut binds = Vec::new();
@@ -132,44 +193,48 @@ impl<'a> Parser<'a> {
                     let body = self.parse_body()?;
 
                     Ok(match s {
-                        "let" => Let { binds, body },
-                        "let*" => LetStar { binds, body },
-                        _ => LetRec { binds, body },
+                        "let" => Let { binds, body, span },
+                        "let*" => LetStar { binds, body, span },
+                        _ => LetRec { binds, body, span },
                     })
                 },
                 "set!" => {
                     let ident = self.next_ident()?;
                     let expr = self.parse_expr()?;
 
-                    Ok(Set { ident, expr: Box::new(expr) })
+                    Ok(Set { ident, expr: Box::new(expr), span })
                 },
                 "quote" => {
-                    Ok(Quote(Box::new(self.parse_s_expr()?)))
+                    Ok(Quote(Box::new(self.parse_s_expr()?), span))
                 },
                 "begin" => {
                     let mut exprs = Vec::new();
                     while !self.peek_if(CloseParen) {
                         exprs.push(self.parse_expr()?);
                     }
-                    Ok(Begin(exprs))
+                    Ok(Begin(exprs, span))
                 },
                 "if" => {
                     let cond = self.parse_expr()?;
                     let expr1 = self.parse_expr()?;
                     let expr2 = self.parse_expr()?;
 
-                    Ok(If { cond: Box::new(cond), expr1: Box::new(expr1), expr2: Box::new(expr2) })
+                    Ok(If { cond: Box::new(cond), expr1: Box::new(expr1), expr2: Box::new(expr2), span })
                 },
                 "cond" => {
-                    let mut cond_then = Vec::new();
-                    while self.next_if(OpenParen) {
-                        let cond = self.parse_expr()?;
-                        let then = self.parse_expr()?;
-                        cond_then.push((cond, then));
-                        self.next_force(CloseParen)?;
-                    }
-                    
-                    Ok(Cond { cond_then })
+                    let cond_then = self.parse_clauses()?;
+
+                    Ok(Cond { cond_then, span })
+                },
+                "guard" => {
+                    self.next_force(OpenParen)?;
+                    let var = self.next_ident()?;
+                    let cond_then = self.parse_clauses()?;
+                    self.next_force(CloseParen)?;
+
+                    let body = self.parse_body()?;
+
+                    Ok(Guard { var, cond_then, body, span })
                 },
                 "and" => {
                     let mut args = Vec::new();
@@ -177,7 +242,7 @@ impl<'a> Parser<'a> {
                         args.push(self.parse_expr()?);
                     }
 
-                    Ok(And { args })
+                    Ok(And { args, span })
                 },
                 "or" => {
                     let mut args = Vec::new();
@@ -185,7 +250,7 @@ impl<'a> Parser<'a> {
                         args.push(self.parse_expr()?);
                     }
 
-                    Ok(Or { args })
+                    Ok(Or { args, span })
                 },
                 "do" => {
                     let mut binds = Vec::new();
@@ -212,12 +277,13 @@ impl<'a> Parser<'a> {
 
                     let body = self.parse_expr()?;
 
-                    Ok(Do { binds, test: Box::new(test), exprs, body: Box::new(body) })
+                    Ok(Do { binds, test: Box::new(test), exprs, body: Box::new(body), span })
                 }
-                _ => Err(format!("{} is unavailable", keyword)),
+                "define" => Err(self.error(self.idx - 1, String::from("define is only allowed at top level or at the beginning of a body"))),
+                _ => Err(self.error(self.idx - 1, format!("{} is unavailable", keyword))),
             }
         } else if self.peek_if(CloseParen) {
-            Ok(Expr::Nil)
+            Ok(Expr::Nil(span))
         } else {
             let proc = self.parse_expr()?;
             let mut args = Vec::new();
@@ -225,91 +291,144 @@ impl<'a> Parser<'a> {
                 args.push(self.parse_expr()?);
             }
 
-            Ok(Apply { proc: Box::new(proc), args })
+            Ok(Apply { proc: Box::new(proc), args, span })
+        }
+    }
+
+    // `(test expr)` clauses as in `cond`
+    fn parse_clauses(&mut self) -> Result<Vec<(Expr, Expr)>, Error> {
+        let mut cond_then = Vec::new();
+        while self.next_if(OpenParen) {
+            let cond = self.parse_expr()?;
+            let then = self.parse_expr()?;
+            cond_then.push((cond, then));
+            self.next_force(CloseParen)?;
         }
+        Ok(cond_then)
     }
 
-    fn parse_s_expr(&mut self) -> Result<Value, String> {
+    fn parse_s_expr(&mut self) -> Result<Value, Error> {
         match self.next_token()? {
             OpenParen => Ok(self.parse_list()?),
+            OpenByteVector => Ok(self.parse_bytevector()?),
+            SingleQuote => Ok(Value::list(vec![Symbol(String::from("quote")), self.parse_s_expr()?])),
+            DatumLabel(label) => {
+                // references inside the datum point at `placeholder`, which then takes over its contents
+                let placeholder = Rc::new(RefCell::new(Cons(Value::Nil, Value::Nil)));
+                self.labels.insert(label, Pair(Rc::clone(&placeholder)));
+                match self.parse_s_expr()? {
+                    Pair(pair) => {
+                        let contents = pair.borrow().clone();
+                        *placeholder.borrow_mut() = contents;
+                        Ok(Pair(placeholder))
+                    },
+                    datum => {
+                        self.labels.insert(label, datum.clone());
+                        Ok(datum)
+                    },
+                }
+            },
+            DatumRef(label) => self.labels.get(&label).cloned().ok_or_else(|| self.error(self.idx - 1, format!("datum label #{}# is undefined", label))),
             Keyword(keyword) => Ok(Symbol(keyword.to_string())),
-            Operator(operator) => Ok(Symbol(operator.to_string())),
             Token::Ident(ident) => Ok(Symbol(ident.to_string())),
+            Token::Int(val) => Ok(Value::Int(val)),
             Token::Num(val) => Ok(Value::Num(val)),
             Token::Bool(val) => Ok(Value::Bool(val)),
-            Token::Str(val) => Ok(Value::Str(Rc::new(val))),
-            token => return Err(format!("unexpected token {:?}", token)),
+            Token::Char(val) => Ok(Value::Char(val)),
+            Token::Str(val) => Ok(Value::literal_str(val)),
+            Invalid(_) => Ok(Value::Nil),
+            token => Err(self.error(self.idx - 1, format!("unexpected '{}'", token))),
         }
     }
 
-    fn parse_list(&mut self) -> Result<Value, String> {
+    fn parse_list(&mut self) -> Result<Value, Error> {
         Ok(if self.next_if(CloseParen) {
             Value::Nil
+        } else if self.next_if(Token::Ident(".")) {
+            let tail = self.parse_s_expr()?;
+            self.next_force(CloseParen)?;
+            tail
         } else {
-            Pair(Rc::new(RefCell::new((self.parse_s_expr()?, self.parse_list()?))))
+            Pair(Rc::new(RefCell::new(Cons(self.parse_s_expr()?, self.parse_list()?))))
         })
     }
 
+    fn parse_bytevector(&mut self) -> Result<Value, Error> {
+        let mut bytes = Vec::new();
+        while !self.next_if(CloseParen) {
+            match self.next_token()? {
+                Token::Int(val) if (0..=255).contains(&val) => bytes.push(val as u8),
+                Invalid(_) => {},
+                token => return Err(self.error(self.idx - 1, format!("{} is not byte", token))),
+            }
+        }
+        Ok(Value::bytevector(bytes))
+    }
+
     fn peek_if(&self, expected: Token<'a>) -> bool {
-        self.idx < self.tokens.len() && self.tokens[self.idx] == expected
+        self.idx < self.tokens.len() && self.tokens[self.idx].0 == expected
     }
 
     fn next_if(&mut self, expected: Token<'a>) -> bool {
-        if self.idx < self.tokens.len() {
-            if self.tokens[self.idx] == expected {
-                self.idx += 1;
-                true
-            } else {
-                false
-            }
+        if self.peek_if(expected) {
+            self.idx += 1;
+            true
         } else {
             false
         }
     }
 
-    fn next_force(&mut self, expected: Token<'a>) -> Result<(), String> {
+    fn next_force(&mut self, expected: Token<'a>) -> Result<(), Error> {
         let actual = self.next_token()?;
         if expected == actual {
             Ok(())
         } else {
-            Err(format!("expect {:?}, actual {:?}",  expected, actual))
+            Err(self.error(self.idx - 1, format!("expect '{}', actual '{}'", expected, actual)))
         }
     }
 
-    fn next_keyword(&mut self) -> Result<&'a str, String> {
+    fn next_keyword(&mut self) -> Result<&'a str, Error> {
         if let Keyword(keyword) = self.peek_token()? {
             self.idx += 1;
             Ok(keyword)
         } else {
-            Err(String::from("expect keyword"))
+            Err(self.error(self.idx, String::from("expect keyword")))
         }
     }
 
-    fn next_ident(&mut self) -> Result<String, String> {
+    fn next_ident(&mut self) -> Result<String, Error> {
         if let Token::Ident(ident) = self.peek_token()? {
             self.idx += 1;
             Ok(ident.to_string())
         } else {
-            Err(String::from("expect identifier"))
+            Err(self.error(self.idx, String::from("expect identifier")))
         }
     }
 
-    fn peek_token(&self) -> Result<Token<'a>, String> {
+    fn peek_token(&self) -> Result<Token<'a>, Error> {
         if self.idx < self.tokens.len() {
-            let token = self.tokens[self.idx].clone();
+            let token = self.tokens[self.idx].0.clone();
             Ok(token)
         } else {
-            Err(String::from("no token"))
+            Err(self.error(self.idx, String::from("unexpected end of input")))
         }
     }
 
-    fn next_token(&mut self) -> Result<Token<'a>, String> {
-        if self.idx < self.tokens.len() {
-            let token = self.tokens[self.idx].clone();
-            self.idx += 1;
-            Ok(token)
-        } else {
-            Err(String::from("no token"))
+    fn next_token(&mut self) -> Result<Token<'a>, Error> {
+        let token = self.peek_token()?;
+        self.idx += 1;
+        Ok(token)
+    }
+
+    // the span of the token at `idx`, or the end of the source past the last token
+    fn span(&self, idx: usize) -> Option<Span> {
+        match self.tokens.get(idx) {
+            Some((_, span)) => Some(span.clone()),
+            None => self.tokens.last().map(|(_, span)| Span { source: Rc::clone(&span.source), offset: span.source.code.trim_end().len() }),
         }
     }
+
+    fn error(&self, idx: usize, message: String) -> Error {
+        Error { span: self.span(idx), ..Error::parse(message) }
+    }
 }
@@ -0,0 +1,396 @@
+use std::fmt;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+
+use crate::data::{self, Value};
+use crate::lexer;
+use crate::parser;
+use crate::error::Error;
+
+thread_local! {
+    static CURRENT_INPUT: RefCell<Port> = RefCell::new(Port::stdin());
+    static CURRENT_OUTPUT: RefCell<Port> = RefCell::new(Port::stdout());
+    static CURRENT_ERROR: RefCell<Port> = RefCell::new(Port::stderr());
+    // whether what was last written to the standard output ends its line
+    static STDOUT_AT_LINE_START: Cell<bool> = const { Cell::new(true) };
+    // every output file port opened, which a reference cycle may keep from being dropped and flushed
+    static OUTPUT_FILES: RefCell<Vec<Weak<RefCell<PortKind>>>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn current_input() -> Port {
+    CURRENT_INPUT.with(|port| port.borrow().clone())
+}
+
+pub fn current_output() -> Port {
+    CURRENT_OUTPUT.with(|port| port.borrow().clone())
+}
+
+pub fn current_error() -> Port {
+    CURRENT_ERROR.with(|port| port.borrow().clone())
+}
+
+// runs `f` with `port` as the current input port, restoring the previous one afterwards
+pub fn with_current_input<T>(port: Port, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT_INPUT.with(|current| current.replace(port));
+    let result = f();
+    CURRENT_INPUT.with(|current| current.replace(prev));
+    result
+}
+
+// runs `f` with `port` as the current output port, restoring the previous one afterwards
+pub fn with_current_output<T>(port: Port, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT_OUTPUT.with(|current| current.replace(port));
+    let result = f();
+    CURRENT_OUTPUT.with(|current| current.replace(prev));
+    result
+}
+
+// flushes the standard output and every output file port still open, before the process exits
+pub fn flush_all() -> Result<(), Error> {
+    let files: Vec<Port> = OUTPUT_FILES.with(|files| {
+        let mut files = files.borrow_mut();
+        files.retain(|file| file.strong_count() > 0);
+        files.iter().filter_map(Weak::upgrade).map(Port).collect()
+    });
+    for file in files {
+        file.flush()?;
+    }
+    io::stdout().flush().map_err(|err| Error::io(err.to_string()))
+}
+
+// ends the line the standard output was left in, so that what the repl prints next starts its own
+pub fn finish_stdout_line() -> Result<(), Error> {
+    if !STDOUT_AT_LINE_START.with(Cell::get) {
+        Port::stdout().write_str("\n")?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+pub struct Port(Rc<RefCell<PortKind>>);
+
+enum PortKind {
+    Input(Input),
+    Output(Output),
+    BinaryInput(VecDeque<u8>),
+    Closed { input: bool },
+}
+
+struct Input {
+    buf: VecDeque<char>,
+    source: Option<Source>,
+}
+
+enum Source {
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+enum Output {
+    Stdout,
+    Stderr,
+    Str(String),
+    File(BufWriter<File>),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_input() {
+            write!(f, "#<input-port>")
+        } else {
+            write!(f, "#<output-port>")
+        }
+    }
+}
+
+impl Port {
+    fn new(kind: PortKind) -> Self {
+        Port(Rc::new(RefCell::new(kind)))
+    }
+
+    pub fn stdin() -> Self {
+        Port::new(PortKind::Input(Input { buf: VecDeque::new(), source: Some(Source::Stdin) }))
+    }
+
+    pub fn stdout() -> Self {
+        Port::new(PortKind::Output(Output::Stdout))
+    }
+
+    pub fn stderr() -> Self {
+        Port::new(PortKind::Output(Output::Stderr))
+    }
+
+    pub fn input_string(string: &str) -> Self {
+        Port::new(PortKind::Input(Input { buf: string.chars().collect(), source: None }))
+    }
+
+    pub fn output_string() -> Self {
+        Port::new(PortKind::Output(Output::Str(String::new())))
+    }
+
+    pub fn input_bytevector(bytes: Vec<u8>) -> Self {
+        Port::new(PortKind::BinaryInput(bytes.into()))
+    }
+
+    pub fn output_bytevector() -> Self {
+        Port::new(PortKind::Output(Output::Bytes(Vec::new())))
+    }
+
+    pub fn input_file(path: &str) -> Result<Self, Error> {
+        let file = File::open(path).map_err(|err| Error::io(format!("can not open {:?}: {}", path, err)))?;
+        Ok(Port::new(PortKind::Input(Input { buf: VecDeque::new(), source: Some(Source::Reader(Box::new(BufReader::new(file)))) })))
+    }
+
+    pub fn output_file(path: &str) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|err| Error::io(format!("can not open {:?}: {}", path, err)))?;
+        let port = Port::new(PortKind::Output(Output::File(BufWriter::new(file))));
+        OUTPUT_FILES.with(|files| files.borrow_mut().push(Rc::downgrade(&port.0)));
+        Ok(port)
+    }
+
+    pub fn ptr_eq(lhs: &Port, rhs: &Port) -> bool {
+        Rc::ptr_eq(&lhs.0, &rhs.0)
+    }
+
+    pub fn is_input(&self) -> bool {
+        matches!(&*self.0.borrow(), PortKind::Input(_) | PortKind::BinaryInput(_) | PortKind::Closed { input: true })
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(&*self.0.borrow(), PortKind::BinaryInput(_) | PortKind::Output(Output::Bytes(_)))
+    }
+
+    pub fn is_output(&self) -> bool {
+        !self.is_input()
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(&*self.0.borrow(), PortKind::Closed { .. })
+    }
+
+    pub fn close(&self) -> Result<(), Error> {
+        if self.is_open() {
+            self.flush()?;
+            let input = self.is_input();
+            *self.0.borrow_mut() = PortKind::Closed { input };
+        }
+        Ok(())
+    }
+
+    fn with_input<T>(&self, f: impl FnOnce(&mut Input) -> Result<T, Error>) -> Result<T, Error> {
+        match &mut *self.0.borrow_mut() {
+            PortKind::Input(input) => f(input),
+            PortKind::BinaryInput(_) => Err(Error::expected("textual port", &Value::Port(self.clone()))),
+            PortKind::Output(_) => Err(Error::expected("input port", &Value::Port(self.clone()))),
+            PortKind::Closed { .. } => Err(Error::expected("open port", &Value::Port(self.clone()))),
+        }
+    }
+
+    fn with_output<T>(&self, f: impl FnOnce(&mut Output) -> Result<T, Error>) -> Result<T, Error> {
+        match &mut *self.0.borrow_mut() {
+            PortKind::Output(output) => f(output),
+            PortKind::Input(_) | PortKind::BinaryInput(_) => Err(Error::expected("output port", &Value::Port(self.clone()))),
+            PortKind::Closed { .. } => Err(Error::expected("open port", &Value::Port(self.clone()))),
+        }
+    }
+
+    pub fn read_char(&self) -> Result<Option<char>, Error> {
+        self.with_input(|input| {
+            input.fill()?;
+            Ok(input.buf.pop_front())
+        })
+    }
+
+    pub fn peek_char(&self) -> Result<Option<char>, Error> {
+        self.with_input(|input| {
+            input.fill()?;
+            Ok(input.buf.front().copied())
+        })
+    }
+
+    // the next datum, lexed a line at a time so that no more input is consumed than it takes, or None at the end of input
+    pub fn read_datum(&self) -> Result<Option<Value>, Error> {
+        loop {
+            let text = self.with_input(|input| Ok(input.buf.iter().collect()))?;
+            let source = data::Source::new("read", text);
+            let datum = match lexer::datum_tokens(&source) {
+                Ok(datum) => datum,
+                Err(err) => {
+                    // drop the offending input so that reading again can make progress
+                    self.clear_input()?;
+                    return Err(err);
+                },
+            };
+            if let Some((tokens, end)) = datum {
+                let len = source.code[..end].chars().count();
+                self.with_input(|input| Ok(input.buf.drain(..len).count()))?;
+                return parser::parse_datum(tokens).map(Some);
+            }
+
+            let more = self.with_input(|input| {
+                let more = input.source.is_some();
+                input.read_source_line()?;
+                Ok(more)
+            })?;
+            if !more {
+                // only atmosphere or an unfinished datum is left
+                self.clear_input()?;
+                let tokens = lexer::tokenize(&source)?;
+                return if tokens.is_empty() { Ok(None) } else { parser::parse_datum(tokens).map(Some) };
+            }
+        }
+    }
+
+    fn clear_input(&self) -> Result<(), Error> {
+        self.with_input(|input| {
+            input.buf.clear();
+            Ok(())
+        })
+    }
+
+    pub fn read_line(&self) -> Result<Option<String>, Error> {
+        let mut line = String::new();
+        loop {
+            match self.read_char()? {
+                Some('\n') => return Ok(Some(line)),
+                Some(c) => line.push(c),
+                None if line.is_empty() => return Ok(None),
+                None => return Ok(Some(line)),
+            }
+        }
+    }
+
+    pub fn read_string(&self, k: usize) -> Result<Option<String>, Error> {
+        let mut string = String::new();
+        while string.chars().count() < k {
+            match self.read_char()? {
+                Some(c) => string.push(c),
+                None => break,
+            }
+        }
+        if string.is_empty() && k > 0 {
+            Ok(None)
+        } else {
+            Ok(Some(string))
+        }
+    }
+
+    pub fn char_ready(&self) -> Result<bool, Error> {
+        self.with_input(|input| Ok(!input.buf.is_empty() || input.source.is_none()))
+    }
+
+    pub fn write_str(&self, string: &str) -> Result<(), Error> {
+        self.with_output(|output| {
+            match output {
+                Output::Stdout => {
+                    if !string.is_empty() {
+                        STDOUT_AT_LINE_START.with(|at_line_start| at_line_start.set(string.ends_with('\n')));
+                    }
+                    io::stdout().write_all(string.as_bytes())
+                },
+                Output::Stderr => io::stderr().write_all(string.as_bytes()),
+                Output::Str(buf) => {
+                    buf.push_str(string);
+                    Ok(())
+                },
+                Output::File(file) => file.write_all(string.as_bytes()),
+                Output::Bytes(_) => return Err(Error::expected("textual port", &Value::Port(self.clone()))),
+            }.map_err(|err| Error::io(err.to_string()))
+        })
+    }
+
+    fn with_binary_input<T>(&self, f: impl FnOnce(&mut VecDeque<u8>) -> T) -> Result<T, Error> {
+        match &mut *self.0.borrow_mut() {
+            PortKind::BinaryInput(bytes) => Ok(f(bytes)),
+            PortKind::Input(_) => Err(Error::expected("binary port", &Value::Port(self.clone()))),
+            PortKind::Output(_) => Err(Error::expected("input port", &Value::Port(self.clone()))),
+            PortKind::Closed { .. } => Err(Error::expected("open port", &Value::Port(self.clone()))),
+        }
+    }
+
+    pub fn read_u8(&self) -> Result<Option<u8>, Error> {
+        self.with_binary_input(|bytes| bytes.pop_front())
+    }
+
+    pub fn peek_u8(&self) -> Result<Option<u8>, Error> {
+        self.with_binary_input(|bytes| bytes.front().copied())
+    }
+
+    pub fn u8_ready(&self) -> Result<bool, Error> {
+        self.with_binary_input(|_| true)
+    }
+
+    pub fn read_bytes(&self, k: usize) -> Result<Option<Vec<u8>>, Error> {
+        self.with_binary_input(|bytes| {
+            if bytes.is_empty() && k > 0 {
+                None
+            } else {
+                Some(bytes.drain(..k.min(bytes.len())).collect())
+            }
+        })
+    }
+
+    pub fn write_bytes(&self, bytes: &[u8]) -> Result<(), Error> {
+        self.with_output(|output| match output {
+            Output::Bytes(buf) => {
+                buf.extend_from_slice(bytes);
+                Ok(())
+            },
+            _ => Err(Error::expected("binary port", &Value::Port(self.clone()))),
+        })
+    }
+
+    pub fn get_output_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.with_output(|output| match output {
+            Output::Bytes(buf) => Ok(buf.clone()),
+            _ => Err(Error::expected("bytevector output port", &Value::Port(self.clone()))),
+        })
+    }
+
+    pub fn get_output_string(&self) -> Result<String, Error> {
+        self.with_output(|output| match output {
+            Output::Str(buf) => Ok(buf.clone()),
+            _ => Err(Error::expected("string output port", &Value::Port(self.clone()))),
+        })
+    }
+
+    pub fn flush(&self) -> Result<(), Error> {
+        match &mut *self.0.borrow_mut() {
+            PortKind::Output(Output::Stdout) => io::stdout().flush().map_err(|err| Error::io(err.to_string())),
+            PortKind::Output(Output::Stderr) => io::stderr().flush().map_err(|err| Error::io(err.to_string())),
+            PortKind::Output(Output::File(file)) => file.flush().map_err(|err| Error::io(err.to_string())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Input {
+    // reads the next line of the source once every buffered character is consumed
+    fn fill(&mut self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            self.read_source_line()?;
+        }
+        Ok(())
+    }
+
+    fn read_source_line(&mut self) -> Result<(), Error> {
+        if let Some(source) = &mut self.source {
+            let mut line = String::new();
+            let read = match source {
+                // so that a prompt written without a newline shows before blocking
+                Source::Stdin => io::stdout().flush().and_then(|_| io::stdin().read_line(&mut line)),
+                Source::Reader(reader) => reader.read_line(&mut line),
+            };
+            match read {
+                Ok(0) => self.source = None,
+                Ok(_) => self.buf.extend(line.chars()),
+                Err(err) => return Err(Error::io(err.to_string())),
+            }
+        }
+        Ok(())
+    }
+}
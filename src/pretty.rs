@@ -0,0 +1,145 @@
+use crate::data::{Value, Printer};
+
+use std::cell::Cell;
+
+thread_local! {
+    static WIDTH: Cell<usize> = const { Cell::new(79) };
+    // whether the repl lays out its results or writes them on one line
+    static ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(|enabled| enabled.get())
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.with(|current| current.set(enabled));
+}
+
+pub fn width() -> usize {
+    WIDTH.with(|width| width.get())
+}
+
+pub fn set_width(width: usize) {
+    WIDTH.with(|current| current.set(width));
+}
+
+// forms whose first `n` arguments stay on the line of the keyword, the rest being an indented body
+const BODY_FORMS: [(&str, usize); 11] = [
+    ("define", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("letrec", 1),
+    ("when", 1),
+    ("unless", 1),
+    ("do", 2),
+    ("begin", 0),
+    ("cond", 0),
+    ("guard", 1),
+];
+
+// `value` as written, broken over lines indented by structure when it does not fit in the line width
+pub fn pretty_print(value: &Value) -> String {
+    let mut out = String::new();
+    if Printer::write().has_labels(value) {
+        out.push_str(&value.write_string());
+    } else {
+        layout(value, &mut out);
+    }
+    out
+}
+
+fn layout(value: &Value, out: &mut String) {
+    let col = column(out);
+    let fits = flat_width(value, width().saturating_sub(col)).is_some();
+    let elems = match value.to_vec() {
+        Ok(elems) if !elems.is_empty() && !fits => elems,
+        _ => {
+            out.push_str(&value.write_string());
+            return;
+        },
+    };
+
+    out.push('(');
+    let head = match &elems[0] {
+        Value::Symbol(symbol) => Some(symbol.as_str()),
+        _ => None,
+    };
+    if let Some((_, mut header)) = BODY_FORMS.iter().find(|(form, _)| Some(*form) == head) {
+        // a named let has its name before the bindings
+        if head == Some("let") && matches!(elems.get(1), Some(Value::Symbol(_))) {
+            header += 1;
+        }
+        out.push_str(head.unwrap());
+        for elem in elems.iter().skip(1).take(header) {
+            out.push(' ');
+            layout(elem, out);
+        }
+        for elem in elems.iter().skip(1 + header) {
+            newline(out, col + 2);
+            layout(elem, out);
+        }
+    } else if let Some(head) = head.filter(|_| elems.len() > 1) {
+        // a call with its arguments aligned under the first one
+        out.push_str(head);
+        out.push(' ');
+        let indent = column(out);
+        for (i, elem) in elems.iter().skip(1).enumerate() {
+            if i > 0 {
+                newline(out, indent);
+            }
+            layout(elem, out);
+        }
+    } else if elems.iter().all(|elem| !matches!(elem, Value::Pair(_))) {
+        // a list of atoms fills each line before wrapping
+        for (i, elem) in elems.iter().enumerate() {
+            let atom = elem.write_string();
+            if i > 0 && column(out) + 1 + atom.chars().count() + 1 > width() {
+                newline(out, col + 1);
+            } else if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(&atom);
+        }
+    } else {
+        for (i, elem) in elems.iter().enumerate() {
+            if i > 0 {
+                newline(out, col + 1);
+            }
+            layout(elem, out);
+        }
+    }
+    out.push(')');
+}
+
+// the width of `value` written on one line, or None as soon as it exceeds `limit`
+fn flat_width(value: &Value, limit: usize) -> Option<usize> {
+    let Value::Pair(_) = value else {
+        return Some(value.write_string().chars().count()).filter(|&width| width <= limit);
+    };
+    // the parentheses, then each element after a space or the opening one
+    let mut width = 1;
+    let mut list = value.clone();
+    loop {
+        list = match list {
+            Value::Pair(pair) => {
+                width += flat_width(&pair.borrow().0, limit.checked_sub(width)?)? + 1;
+                let cdr = pair.borrow().1.clone();
+                cdr
+            },
+            Value::Nil => return Some(width).filter(|&width| width <= limit),
+            // the tail of an improper list follows ". " and then the closing parenthesis
+            tail => return Some(width + 3 + flat_width(&tail, limit.checked_sub(width + 3)?)?).filter(|&width| width <= limit),
+        };
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(indent));
+}
+
+fn column(out: &str) -> usize {
+    out.rsplit('\n').next().map_or(0, |line| line.chars().count())
+}
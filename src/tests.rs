@@ -0,0 +1,379 @@
+use crate::data::{Env, Source};
+use crate::port::{self, Port};
+use crate::editor::ReplHelper;
+use crate::{interprete, Failure, Options, Preload};
+
+use rustyline::completion::Completer;
+use rustyline::history::DefaultHistory;
+use rustyline::Context;
+
+// runs `code` as a program, returning what it printed or the message of the error that stopped it
+fn run(code: &str) -> Result<String, String> {
+    let output = Port::output_string();
+    let result = port::with_current_output(output.clone(), || interprete(Source::new("test", code.to_string()), &mut Env::new()));
+    let printed = output.get_output_string().map_err(|err| err.to_string())?;
+    match result {
+        Ok(()) => Ok(printed),
+        Err(Failure::Syntax(errors)) => Err(errors.iter().map(|err| err.message()).collect::<Vec<_>>().join("\n")),
+        Err(Failure::Runtime(err) | Failure::IO(err)) => Err(err.message()),
+        Err(Failure::Exit(status)) => Err(format!("exit {}", status)),
+        Err(Failure::Usage(usage)) => Err(usage),
+    }
+}
+
+#[test]
+fn prints_top_level_values() {
+    assert_eq!(run("(+ 1 2) (define x 4) (list x \"a\" #\\b)"), Ok(String::from("3\n(4 \"a\" #\\b)\n")));
+}
+
+#[test]
+fn captures_display_and_write() {
+    assert_eq!(run("(define (f) (display \"a b\") (write \"a b\") (newline)) (f)"), Ok(String::from("a b\"a b\"\n")));
+}
+
+#[test]
+fn output_string_ports() {
+    let code = "
+        (define port (open-output-string))
+        (begin
+          (write 'sym port)
+          (display \" \" port)
+          (write-char #\\z port)
+          (get-output-string port))
+        (with-output-to-string (lambda () (display 42)))
+        (call-with-output-string (lambda (port) (write \"q\" port)))";
+    assert_eq!(run(code), Ok(String::from("\"sym z\"\n\"42\"\n\"\\\"q\\\"\"\n")));
+}
+
+#[test]
+fn input_string_ports() {
+    let code = "
+        (define port (open-input-string \"ab\ncd\"))
+        (read-char port)
+        (read-line port)
+        (read-line port)
+        (eof-object? (read-line port))";
+    assert_eq!(run(code), Ok(String::from("#\\a\n\"b\"\n\"cd\"\n#t\n")));
+}
+
+#[test]
+fn output_before_an_error_is_kept() {
+    let output = Port::output_string();
+    let result = port::with_current_output(output.clone(), || interprete(Source::new("test", String::from("(display 1) (car 2)")), &mut Env::new()));
+    assert!(matches!(result, Err(Failure::Runtime(_))));
+    assert_eq!(output.get_output_string().ok(), Some(String::from("1")));
+}
+
+#[test]
+fn reports_runtime_errors() {
+    assert_eq!(run("(car 2)"), Err(String::from("car: expected pair, got 2")));
+    assert_eq!(run("(error \"bad thing:\" 1 'x)"), Err(String::from("bad thing: 1 x")));
+}
+
+#[test]
+fn read_uses_the_datum_grammar() {
+    let code = r#"
+        (define port (open-input-string "(a \"b)\" #\\( 'c) #| x |# #u8(1 2) #0=(1 . #0#) ; rest
+          #xff \"\"   "))
+        (read port)
+        (read port)
+        (car (cdr (cdr (read port))))
+        (read port)
+        (read port)
+        (eof-object? (read port))"#;
+    assert_eq!(run(code), Ok(String::from("(a \"b)\" #\\( (quote c))\n#u8(1 2)\n1\n255\n\"\"\n#t\n")));
+}
+
+#[test]
+fn read_consumes_one_datum() {
+    let code = r#"
+        (define port (open-input-string "1 (2 3)x"))
+        (read port)
+        (read-char port)
+        (read port)
+        (read-char port)"#;
+    assert_eq!(run(code), Ok(String::from("1\n#\\space\n(2 3)\n#\\x\n")));
+}
+
+#[test]
+fn read_errors() {
+    assert_eq!(run("(read (open-input-string \"(1 2\"))"), Err(String::from("read: unexpected end of input")));
+    assert_eq!(run("(read (open-input-string \")\"))"), Err(String::from("read: unexpected ')'")));
+}
+
+#[test]
+fn pretty_prints_cond_clauses_one_per_line() {
+    let code = "
+        (pretty-print-width 30)
+        (pretty-print '(define (f x) (cond ((= x 1) 'one) (else 'many))))
+        (pretty-print-width 79)";
+    assert_eq!(run(code), Ok(String::from("(define (f x)\n  (cond\n    ((= x 1) (quote one))\n    (else (quote many))))\n")));
+}
+
+#[test]
+fn errors_point_at_the_failing_form() {
+    let locate = |code: &str| match interprete(Source::new("test", code.to_string()), &mut Env::new()) {
+        Err(Failure::Runtime(err)) => err.span.map(|span| span.to_string()),
+        _ => None,
+    };
+    assert_eq!(locate("(define (f x)\n  (do ((i 0 (+ i 1))) (x 0) i))\n(f 1)"), Some(String::from("test:2:3")));
+    assert_eq!(locate("(let ((x 1))\n  (car x))"), Some(String::from("test:2:3")));
+    assert_eq!(locate("(begin 1 (undefined))"), Some(String::from("test:1:11")));
+}
+
+#[test]
+fn reports_lexical_and_syntax_errors_together() {
+    let code = "(define x #q)\n(if 1)\n(display \"\\x zz;\")\n(g #\\bogus 1)\n(define #z 1)";
+    let errors = match interprete(Source::new("test", code.to_string()), &mut Env::new()) {
+        Err(Failure::Syntax(errors)) => errors.iter().map(|err| format!("{}: {}", err.span.as_ref().unwrap(), err.message())).collect(),
+        _ => Vec::new(),
+    };
+    assert_eq!(errors, [
+        "test:1:11: invalid token '#q'",
+        "test:2:6: unexpected ')'",
+        "test:3:10: invalid escape '\\x zz;'",
+        "test:4:4: invalid character name 'bogus'",
+        "test:5:9: invalid token '#z'",
+    ]);
+}
+
+#[test]
+fn definitions_shadow_built_ins() {
+    let code = "
+        (define (count lst) (if (null? lst) 0 (+ 1 (count (cdr lst)))))
+        (count '(a b c))
+        (let ((min 0) (max 10)) (list min max))
+        (max 1 5)
+        (define (f display) display)
+        (f 3)";
+    assert_eq!(run(code), Ok(String::from("3\n(0 10)\n5\n3\n")));
+}
+
+#[test]
+fn list_procedures_on_mutated_long_and_circular_lists() {
+    assert_eq!(run("(define l (list 1 2)) (find (lambda (x) (set-car! l 9) #f) l) l"), Ok(String::from("#f\n(9 2)\n")));
+    assert_eq!(run("(last-pair (iota 1000000))"), Ok(String::from("(999999)\n")));
+    assert_eq!(run("(define c (list 1 2 3)) (begin (set-cdr! (cdr (cdr c)) c) (last-pair c))"), Err(String::from("last-pair: expected finite list, got #0=(1 2 3 . #0#)")));
+}
+
+#[test]
+fn string_index_counts_characters() {
+    assert_eq!(run("(string-index \"héllo\" #\\l) (string-index \"abc\" #\\z) (string-index \"ab1\" (lambda (c) (equal? c #\\1)))"), Ok(String::from("2\n#f\n2\n")));
+}
+
+#[test]
+fn unspecified_values_are_not_echoed() {
+    let code = "
+        (define s (make-string 2 #\\a))
+        (define p (cons 1 2))
+        (display \"hi\")
+        (newline)
+        (string-set! s 0 #\\b)
+        (set-car! p s)
+        (set! s 1)
+        p
+        (list (newline))";
+    assert_eq!(run(code), Ok(String::from("hi\n(\"ba\" . 2)\n\n(#<unspecified>)\n")));
+}
+
+#[test]
+fn handlers_run_where_errors_are_raised() {
+    assert_eq!(run("(with-exception-handler (lambda (e) 42) (lambda () (+ (raise-continuable 'c) 1)))"), Ok(String::from("43\n")));
+    let code = "(with-exception-handler (lambda (e) (display \"handled \")) (lambda () (raise 'oops)))";
+    assert_eq!(run(code), Err(String::from("exception handler returned oops")));
+    let code = "(with-exception-handler (lambda (e) (display 1)) (lambda () (with-exception-handler (lambda (e) (display 2)) (lambda () (car 1)))))";
+    assert_eq!(run(code), Err(String::from("exception handler returned #<error exception handler returned #<error car: expected pair, got 1>>")));
+}
+
+#[test]
+fn guard_catches_errors() {
+    let code = "
+        (guard (e ((symbol? e) (list 'caught e))) (raise 'boom))
+        (guard (e (else (error-object-message e))) (error \"bad\" 1))
+        (guard (e (#t (error-object-irritants e))) (car 1))
+        (guard (e ((string? e) e)) (guard (e ((symbol? e) e)) (raise \"inner\")))
+        (guard (e (#t (error-object-message e))) (with-exception-handler (lambda (e) 0) (lambda () (raise 'x))))";
+    assert_eq!(run(code), Ok(String::from("(caught boom)\n\"bad\"\n(1)\n\"inner\"\n\"exception handler returned\"\n")));
+    assert_eq!(run("(guard (e ((string? e) e)) (raise 'unhandled))"), Err(String::from("uncaught unhandled")));
+}
+
+#[test]
+fn completes_after_non_ascii_text() {
+    let helper = ReplHelper::new(Env::new());
+    let history = DefaultHistory::new();
+    let complete = |line: &str| helper.complete(line, line.len(), &Context::new(&history)).ok();
+    assert_eq!(complete("\"hé"), Some((4, Vec::new())));
+    assert_eq!(complete("(é string-ref"), Some((4, vec![String::from("string-ref")])));
+    assert_eq!(complete("(é"), Some((3, Vec::new())));
+}
+
+#[test]
+fn exit_flushes_output_files_and_checks_its_status() {
+    let path = std::env::temp_dir().join(format!("scheme-processor-exit-{}.txt", std::process::id()));
+    let code = format!("(define p (open-output-file {:?})) (define (g) p) (write \"data\" p) (exit 3)", path.to_string_lossy());
+    assert_eq!(run(&code), Err(String::from("exit 3")));
+    port::flush_all().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).ok(), Some(String::from("\"data\"")));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(run("(exit 256)"), Err(String::from("exit: exit status 256 is out of range")));
+}
+
+#[test]
+fn exact_integers_at_their_limits() {
+    let code = "
+        (define most-negative (- 0 9223372036854775807 1))
+        (quotient most-negative -1)
+        (remainder most-negative -1)
+        (modulo most-negative -1)
+        (floor/ most-negative -1)
+        (abs most-negative)
+        (- most-negative)
+        (/ most-negative -1)
+        (gcd most-negative -1)
+        (exact-integer-sqrt 9223372036854775807)
+        (+ 9223372036854775807 1)
+        (* 4294967296 4294967296)
+        (floor/ -7 2)
+        (truncate/ -7 2)
+        (exact? (quotient 7 2))";
+    let expected = "\
+        9.223372036854776e18\n0\n0\n9.223372036854776e18 0\n9.223372036854776e18\n9.223372036854776e18\n9.223372036854776e18\n1\n\
+        3037000499 5928526806\n9.223372036854776e18\n1.8446744073709552e19\n-4 1\n-3 -1\n#t\n";
+    assert_eq!(run(code), Ok(String::from(expected)));
+}
+
+#[test]
+fn number_conversions_round_trip_through_radixes() {
+    let code = "
+        (define (map f l) (if (null? l) '() (cons (f (car l)) (map f (cdr l)))))
+        (map (lambda (radix) (string->number (number->string -12345 radix) radix)) '(2 8 10 16))
+        (number->string 255 16)
+        (number->string -5 2)
+        (string->number \"#xFF\")
+        (string->number \"#b-101\")
+        (string->number \"#o17\")
+        (string->number \"1e3\")
+        (string->number \".5\")
+        (map string->number '(\"\" \"abc\" \"12\" \"-\" \"1.2.3\" \"#xZZ\"))
+        (string->number \"12\" 2)";
+    let expected = "(-12345 -12345 -12345 -12345)\n\"ff\"\n\"-101\"\n255\n-5\n15\n1000.0\n0.5\n(#f #f 12 #f #f #f)\n#f\n";
+    assert_eq!(run(code), Ok(String::from(expected)));
+}
+
+#[test]
+fn datum_labels_for_shared_and_cyclic_data() {
+    let code = "
+        (define c (list 1 2 3))
+        (set-cdr! (cdr (cdr c)) c)
+        c
+        (define s (list 'a 'b))
+        (list s s)
+        (define port (open-output-string))
+        (write-shared (list s s) port)
+        (write-simple (list s s) port)
+        (get-output-string port)
+        (define r (read (open-input-string \"#0=(1 2 3 . #0#)\")))
+        (eq? (cdr (cdr (cdr r))) r)
+        (list (list? c) (equal? r c) (equal? r (read (open-input-string \"#0=(1 2 . #0#)\"))))
+        (define shared (read (open-input-string \"(#1=(x) #1#)\")))
+        (eq? (car shared) (car (cdr shared)))
+        (define out (open-output-string))
+        (write c out)
+        (equal? (read (open-input-string (get-output-string out))) c)";
+    let expected = "#0=(1 2 3 . #0#)\n((a b) (a b))\n\"(#0=(a b) #0#)((a b) (a b))\"\n#t\n(#f #t #f)\n#t\n#t\n";
+    assert_eq!(run(code), Ok(String::from(expected)));
+}
+
+#[test]
+fn format_directives_with_width_and_precision() {
+    let code = "
+        (format #f \"~a|~s|~%\" \"x\" \"x\")
+        (format #f \"~5a|~4a|~3s|\" 'ab \"é\" #\\z)
+        (format #f \"~5,2f|~,3f|~8,3f|~f\" 3.14159 2 -1.5 0.25)
+        (format #f \"~d ~x ~o ~b ~4d|\" 255 255 255 5 7)
+        (format #f \"~~~c\" #\\q)
+        (begin (format #t \"to ~a\" 'stdout) (newline))
+        (define port (open-output-string))
+        (begin (format port \"~a-~a\" 1 2) (get-output-string port))";
+    let expected = "\"x|\\\"x\\\"|\\n\"\n\"ab   |é   |#\\\\z|\"\n\" 3.14|2.000|  -1.500|0.25\"\n\"255 ff 377 101    7|\"\n\"~q\"\nto stdout\n\"1-2\"\n";
+    assert_eq!(run(code), Ok(String::from(expected)));
+    assert_eq!(run("(format #f \"~a ~a\" 1)"), Err(String::from("format: too few arguments for ~a")));
+    assert_eq!(run("(format #f \"~q\" 1)"), Err(String::from("format: unknown format directive ~q")));
+}
+
+#[test]
+fn backtraces_list_the_calls_an_error_left() {
+    let code = "(define (inner x) (car x))\n(define (middle x) (inner x))\n(define (outer x) (middle x))\n(outer 5)";
+    let report = match interprete(Source::new("test", code.to_string()), &mut Env::new()) {
+        Err(Failure::Runtime(err)) => err.to_string(),
+        _ => String::new(),
+    };
+    let expected = "\
+test:1:19: car: expected pair, got 5
+  |
+1 | (define (inner x) (car x))
+  |                   ^
+  in inner (test:1:19)
+  called from middle (test:2:20)
+  called from outer (test:3:19)
+  called from top level (test:4:1)";
+    assert_eq!(report, expected);
+
+    let code = "
+        (define (loop n) (if (= n 0) (car n) (+ 1 (loop (- n 1)))))
+        (guard (e (#t (write (error-object-trace e)))) (loop 2))";
+    let expected = "((\"loop\" \"test:2:38\") (\"loop\" \"test:2:51\") (\"loop\" \"test:2:51\") (\"top level\" \"test:3:56\"))";
+    assert_eq!(run(code), Ok(String::from(expected)));
+}
+
+fn parse_options(args: &[&str]) -> Result<Options, Failure> {
+    Options::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn parses_command_line_options() {
+    let Ok(options) = parse_options(&["-e", "(display 1)", "-l", "lib.scm", "-i", "main.scm", "-e", "x"]) else { panic!("options rejected") };
+    assert!(matches!(options.preloads.as_slice(), [Preload::Eval(expr), Preload::Load(file)] if expr == "(display 1)" && file == "lib.scm"));
+    assert!(options.interactive);
+    assert_eq!(options.program.as_deref(), Some("main.scm"));
+    // options after the program belong to the program
+    assert_eq!(options.args, ["-e", "x"]);
+
+    let Ok(options) = parse_options(&["-", "--", "-i"]) else { panic!("options rejected") };
+    assert_eq!(options.program.as_deref(), Some("-"));
+    assert_eq!(options.args, ["-i"]);
+    assert!(!options.interactive);
+
+    let Ok(options) = parse_options(&["--", "-e", "a"]) else { panic!("options rejected") };
+    assert!(options.preloads.is_empty() && options.program.is_none());
+    assert_eq!(options.args, ["-e", "a"]);
+
+    assert!(matches!(parse_options(&["-e"]), Err(Failure::Usage(usage)) if usage == "option -e needs an argument, try --help"));
+    assert!(matches!(parse_options(&["-x", "main.scm"]), Err(Failure::Usage(usage)) if usage == "unknown option -x, try --help"));
+}
+
+// runs `code`, returning what it printed even when it stopped early
+fn run_printing(code: &str) -> (Result<(), Failure>, String) {
+    let output = Port::output_string();
+    let result = port::with_current_output(output.clone(), || interprete(Source::new("test", code.to_string()), &mut Env::new()));
+    (result, output.get_output_string().unwrap_or_default())
+}
+
+#[test]
+fn exit_statuses_and_dynamic_wind() {
+    assert_eq!(run("(exit)"), Err(String::from("exit 0")));
+    assert_eq!(run("(exit #t)"), Err(String::from("exit 0")));
+    assert_eq!(run("(exit #f)"), Err(String::from("exit 1")));
+    assert_eq!(run("(exit 255)"), Err(String::from("exit 255")));
+    assert_eq!(run("(exit -1)"), Err(String::from("exit: exit status -1 is out of range")));
+    assert_eq!(run("(exit \"1\")"), Err(String::from("exit: expected integer or boolean, got \"1\"")));
+
+    let wind = "(define (wind thunk) (dynamic-wind (lambda () (display \"in \")) thunk (lambda () (display \"out \"))))";
+    assert_eq!(run(&format!("{wind} (wind (lambda () 1))")), Ok(String::from("in out 1\n")));
+    let (result, printed) = run_printing(&format!("{wind} (wind (lambda () (car 1)))"));
+    assert!(matches!(result, Err(Failure::Runtime(_))));
+    assert_eq!(printed, "in out ");
+    let (result, printed) = run_printing(&format!("{wind} (wind (lambda () (exit 4) (display \"after\")))"));
+    assert!(matches!(result, Err(Failure::Exit(4))));
+    assert_eq!(printed, "in out ");
+}